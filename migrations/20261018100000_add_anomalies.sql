@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE anomalies (
+    id SERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    details TEXT NOT NULL,
+    expected_wh DOUBLE PRECISION,
+    actual_wh DOUBLE PRECISION,
+    started_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    last_seen_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    resolved_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE INDEX anomalies_started_at_idx ON anomalies (started_at DESC);
+
+-- only one open anomaly of each kind at a time
+CREATE UNIQUE INDEX anomalies_open_kind_idx ON anomalies (kind) WHERE resolved_at IS NULL;
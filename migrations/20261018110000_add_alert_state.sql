@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE alert_state (
+    rule TEXT PRIMARY KEY,
+    breaches INTEGER NOT NULL DEFAULT 0,
+    firing BOOLEAN NOT NULL DEFAULT false,
+    message TEXT,
+    fired_at TIMESTAMP WITHOUT TIME ZONE,
+    resolved_at TIMESTAMP WITHOUT TIME ZONE,
+    updated_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE daily_summaries (
+    date DATE PRIMARY KEY,
+    total_kwh DOUBLE PRECISION NOT NULL,
+    peak_w DOUBLE PRECISION NOT NULL,
+    peak_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    avg_uv_level DOUBLE PRECISION,
+    avg_temperature DOUBLE PRECISION,
+    income DOUBLE PRECISION,
+    currency TEXT,
+    posted_at TIMESTAMP WITHOUT TIME ZONE,
+    updated_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE solar_data_tsdb
+ADD station_id TEXT;
+
+CREATE INDEX solar_data_tsdb_station_id_idx ON solar_data_tsdb (station_id, time DESC);
@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE subscriptions (
+    user_id BIGINT NOT NULL,
+    kind TEXT NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    PRIMARY KEY (user_id, kind)
+);
+
+CREATE INDEX subscriptions_kind_idx ON subscriptions (kind);
+
+CREATE TABLE forecast_posts (
+    date DATE PRIMARY KEY,
+    posted_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
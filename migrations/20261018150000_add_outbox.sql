@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE outbox (
+    id BIGSERIAL PRIMARY KEY,
+    destination TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    last_error TEXT,
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    delivered_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE INDEX outbox_pending_idx ON outbox (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX outbox_destination_idx ON outbox (destination, created_at DESC);
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE pvoutput_state (
+    id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+    -- local start of the first interval not yet uploaded with addstatus
+    status_until TIMESTAMP WITHOUT TIME ZONE,
+    -- last local date uploaded with addoutput
+    output_until DATE,
+    updated_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE influx_state (
+    id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+    -- time of the newest reading written to InfluxDB
+    exported_until TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    updated_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE job_runs (
+    id BIGSERIAL PRIMARY KEY,
+    job TEXT NOT NULL,
+    started_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    finished_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    -- success, partial or failure
+    outcome TEXT NOT NULL,
+    steps JSONB NOT NULL DEFAULT '[]',
+    error TEXT
+);
+
+CREATE INDEX job_runs_job_idx ON job_runs (job, started_at DESC);
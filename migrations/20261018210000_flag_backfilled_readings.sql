@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Backfilled readings hold a power chart point rather than a SEMS response,
+-- so readers of `raw_data` skip them.
+ALTER TABLE solar_data_tsdb ADD COLUMN backfilled BOOLEAN NOT NULL DEFAULT false;
+
+UPDATE solar_data_tsdb SET backfilled = true WHERE raw_data->>'source' = 'backfill';
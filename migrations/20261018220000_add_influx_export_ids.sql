@@ -0,0 +1,19 @@
+-- Add migration script here
+-- Ids are only assigned to readings stored from now on, so the InfluxDB
+-- export can break ties between readings with the same time and find
+-- backfilled readings older than its cursor. Existing rows keep a NULL id:
+-- adding the column without a default leaves compressed chunks untouched
+-- and needs no rewrite.
+CREATE SEQUENCE solar_data_tsdb_id_seq;
+
+ALTER TABLE solar_data_tsdb ADD COLUMN id BIGINT;
+ALTER TABLE solar_data_tsdb ALTER COLUMN id SET DEFAULT nextval('solar_data_tsdb_id_seq');
+ALTER SEQUENCE solar_data_tsdb_id_seq OWNED BY solar_data_tsdb.id;
+
+ALTER TABLE influx_state
+    -- id of the newest reading written at `exported_until`
+    ADD COLUMN exported_id BIGINT NOT NULL DEFAULT 0,
+    -- id of the newest backfilled reading written to InfluxDB
+    ADD COLUMN backfill_exported_id BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX solar_data_tsdb_backfilled_idx ON solar_data_tsdb (id) WHERE backfilled;
@@ -0,0 +1,320 @@
+use std::sync::Arc;
+
+use sqlx::{PgPool, prelude::FromRow};
+use tracing::{Instrument, instrument};
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    channel::message::Embed,
+    id::{Id, marker::ChannelMarker},
+};
+use twilight_util::builder::embed::EmbedBuilder;
+use types::{AlertRule, AlertRules, AlertState, AlertTransition};
+
+use crate::{
+    anomaly::types::AnomalyKind,
+    background::RunReport,
+    metrics::Timed,
+    subscriptions::{Subscriptions, types::SubscriptionKind},
+    webhooks::{
+        Webhooks,
+        types::{AlertEvent, WebhookEvent},
+    },
+};
+
+pub mod types;
+
+#[derive(Clone)]
+pub struct AlertManager {
+    db: PgPool,
+    /// `None` when the Discord integration is disabled.
+    http: Option<Arc<HttpClient>>,
+    rules: AlertRules,
+    subscriptions: Subscriptions,
+    webhooks: Webhooks,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AlertError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("a discord error occurred: {0}")]
+    Discord(#[from] twilight_http::Error),
+    #[error("unknown error occurred: {0}")]
+    Unknown(#[from] anyhow::Error),
+}
+
+impl AlertManager {
+    pub fn new(
+        db: PgPool,
+        http: Option<Arc<HttpClient>>,
+        rules: AlertRules,
+        subscriptions: Subscriptions,
+        webhooks: Webhooks,
+    ) -> Self {
+        Self {
+            db,
+            http,
+            rules,
+            subscriptions,
+            webhooks,
+        }
+    }
+
+    /// Evaluates every rule against the latest poll, sending a notification
+    /// whenever a rule starts firing or recovers.
+    #[instrument(skip_all)]
+    pub async fn evaluate(&self, report: &RunReport) -> Result<(), AlertError> {
+        let checks = [
+            (
+                AlertRule::InverterOffline,
+                self.check_inverter_offline().await?,
+            ),
+            (
+                AlertRule::ZeroProduction,
+                self.check_open_anomaly(AnomalyKind::ZeroOutput).await?,
+            ),
+            (AlertRule::DailyTotalLow, self.check_daily_total().await?),
+            (
+                AlertRule::SemsLoginFailing,
+                report
+                    .login_error
+                    .as_ref()
+                    .map(|e| format!("SEMS login failed: {e}")),
+            ),
+            (
+                AlertRule::UvFeedDown,
+                report
+                    .uv_error
+                    .as_ref()
+                    .map(|e| format!("ARPANSA UV feed failed: {e}")),
+            ),
+            (
+                AlertRule::WeatherFeedDown,
+                report
+                    .weather_error
+                    .as_ref()
+                    .map(|e| format!("BOM observations failed: {e}")),
+            ),
+        ];
+
+        for (rule, breach) in checks {
+            if let Some(transition) = self.transition(rule, breach).await? {
+                tracing::warn!("alert {}: {transition:?}", rule.as_str());
+                self.notify(rule, &transition).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn threshold(&self, rule: AlertRule) -> i32 {
+        match rule {
+            AlertRule::SemsLoginFailing | AlertRule::UvFeedDown | AlertRule::WeatherFeedDown => {
+                self.rules.failure_threshold
+            }
+            _ => 1,
+        }
+    }
+
+    async fn transition(
+        &self,
+        rule: AlertRule,
+        breach: Option<String>,
+    ) -> Result<Option<AlertTransition>, AlertError> {
+        let state: Option<AlertState> =
+            sqlx::query_as("SELECT breaches, firing FROM alert_state WHERE rule = $1")
+                .bind(rule.as_str())
+                .fetch_optional(&self.db)
+                .timed("alert_state")
+                .await?;
+
+        let (breaches, was_firing) = state.map(|s| (s.breaches, s.firing)).unwrap_or_default();
+
+        match breach {
+            Some(message) => {
+                let breaches = breaches + 1;
+                let firing = was_firing || breaches >= self.threshold(rule);
+
+                sqlx::query(
+                    r#"INSERT INTO alert_state (rule, breaches, firing, message, fired_at)
+                       VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN now() END)
+                       ON CONFLICT (rule) DO UPDATE
+                       SET breaches = $2, firing = $3, message = $4,
+                           fired_at = CASE WHEN $5 THEN now() ELSE alert_state.fired_at END,
+                           updated_at = now()"#,
+                )
+                .bind(rule.as_str())
+                .bind(breaches)
+                .bind(firing)
+                .bind(&message)
+                .bind(firing && !was_firing)
+                .execute(&self.db)
+                .timed("alert_state_breach")
+                .await?;
+
+                Ok((firing && !was_firing).then_some(AlertTransition::Fired(message)))
+            }
+            None if breaches > 0 || was_firing => {
+                sqlx::query(
+                    r#"UPDATE alert_state
+                       SET breaches = 0, firing = false, updated_at = now(),
+                           resolved_at = CASE WHEN firing THEN now() ELSE resolved_at END
+                       WHERE rule = $1"#,
+                )
+                .bind(rule.as_str())
+                .execute(&self.db)
+                .timed("alert_state_clear")
+                .await?;
+
+                Ok(was_firing.then_some(AlertTransition::Resolved))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Fires when readings stop arriving. Readings of no output are left to
+    /// the zero production rule.
+    async fn check_inverter_offline(&self) -> Result<Option<String>, AlertError> {
+        #[derive(FromRow)]
+        struct Row {
+            mins_since: Option<f64>,
+        }
+
+        let row: Row = sqlx::query_as(
+            "SELECT (EXTRACT(EPOCH FROM (NOW() - max(time))) / 60)::float8 AS mins_since FROM solar_data_tsdb",
+        )
+        .fetch_one(&self.db)
+        .instrument(tracing::info_span!("alert_latest_reading"))
+        .timed("alert_latest_reading")
+        .await?;
+
+        Ok(row
+            .mins_since
+            .filter(|mins_since| *mins_since >= self.rules.no_data_mins as f64)
+            .map(|mins_since| format!("no new data for {mins_since:.0} mins")))
+    }
+
+    async fn check_open_anomaly(&self, kind: AnomalyKind) -> Result<Option<String>, AlertError> {
+        #[derive(FromRow)]
+        struct Row {
+            details: String,
+        }
+
+        let row: Option<Row> =
+            sqlx::query_as("SELECT details FROM anomalies WHERE kind = $1 AND resolved_at IS NULL")
+                .bind(kind.as_str())
+                .fetch_optional(&self.db)
+                .timed("alert_open_anomaly")
+                .await?;
+
+        Ok(row.map(|r| r.details))
+    }
+
+    /// Compares today's total so far with the median total previous days had
+    /// reached by the same time of day.
+    async fn check_daily_total(&self) -> Result<Option<String>, AlertError> {
+        #[derive(FromRow)]
+        struct Row {
+            today_kwh: Option<f64>,
+            expected_kwh: Option<f64>,
+        }
+
+        let row: Row = sqlx::query_as(
+            r#"SELECT
+                   (SELECT (raw_data->'data'->'kpi'->>'power')::float8
+                    FROM solar_data_tsdb
+                    WHERE (time + '8 hour')::date = (NOW() + '8 hour')::date AND NOT backfilled
+                    ORDER BY time DESC LIMIT 1) AS today_kwh,
+                   (SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY day_kwh)
+                    FROM (
+                        SELECT DISTINCT ON ((time + '8 hour')::date) (raw_data->'data'->'kpi'->>'power')::float8 AS day_kwh
+                        FROM solar_data_tsdb
+                        WHERE time > NOW() - INTERVAL '30 days'
+                          AND (time + '8 hour')::date < (NOW() + '8 hour')::date
+                          AND (time + '8 hour')::time <= (NOW() + '8 hour')::time
+                          AND NOT backfilled
+                        ORDER BY (time + '8 hour')::date, time DESC
+                    ) days) AS expected_kwh"#,
+        )
+        .fetch_one(&self.db)
+        .instrument(tracing::info_span!("alert_daily_total"))
+        .timed("alert_daily_total")
+        .await?;
+
+        let (Some(today_kwh), Some(expected_kwh)) = (row.today_kwh, row.expected_kwh) else {
+            return Ok(None);
+        };
+
+        // too early in the day for the comparison to mean anything
+        if expected_kwh < 1.0 {
+            return Ok(None);
+        }
+
+        let percent = today_kwh / expected_kwh * 100.0;
+        Ok((percent < self.rules.daily_total_percent).then(|| {
+            format!("{today_kwh:.1} kWh so far, {percent:.0}% of the usual {expected_kwh:.1} kWh")
+        }))
+    }
+
+    async fn notify(
+        &self,
+        rule: AlertRule,
+        transition: &AlertTransition,
+    ) -> Result<(), AlertError> {
+        let embed = match transition {
+            AlertTransition::Fired(message) => EmbedBuilder::new()
+                .title(format!("🔴 {}", rule.title()))
+                .description(message)
+                .color(0xcc6666),
+            AlertTransition::Resolved => EmbedBuilder::new()
+                .title(format!("✅ Resolved: {}", rule.title()))
+                .color(0x40944c),
+        }
+        .validate()
+        .map_err(anyhow::Error::from)?
+        .build();
+
+        if let Some(http) = &self.http
+            && let Some(channel_id) = self.rules.channel_id
+            && let Err(e) = Self::send(http, channel_id, &embed).await
+        {
+            tracing::error!("error sending alert to channel {channel_id}: {e}");
+        }
+
+        if let Err(e) = self
+            .subscriptions
+            .notify(SubscriptionKind::OutageAlerts, &embed)
+            .await
+        {
+            tracing::error!("error sending alert to subscribers: {e}");
+        }
+
+        let (status, message) = match transition {
+            AlertTransition::Fired(message) => ("fired", Some(message.as_str())),
+            AlertTransition::Resolved => ("resolved", None),
+        };
+        let event = AlertEvent {
+            rule: rule.as_str(),
+            title: rule.title(),
+            status,
+            message,
+        };
+        if let Err(e) = self.webhooks.emit(WebhookEvent::Alert, &event).await {
+            tracing::error!("error queueing alert webhooks: {e}");
+        }
+
+        Ok(())
+    }
+
+    async fn send(
+        http: &HttpClient,
+        channel_id: Id<ChannelMarker>,
+        embed: &Embed,
+    ) -> Result<(), AlertError> {
+        http.create_message(channel_id)
+            .embeds(std::slice::from_ref(embed))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Deserialize;
+use sqlx::prelude::FromRow;
+use twilight_model::id::{Id, marker::ChannelMarker};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertRule {
+    InverterOffline,
+    ZeroProduction,
+    DailyTotalLow,
+    SemsLoginFailing,
+    UvFeedDown,
+    WeatherFeedDown,
+}
+
+impl AlertRule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertRule::InverterOffline => "inverter_offline",
+            AlertRule::ZeroProduction => "zero_production",
+            AlertRule::DailyTotalLow => "daily_total_low",
+            AlertRule::SemsLoginFailing => "sems_login_failing",
+            AlertRule::UvFeedDown => "uv_feed_down",
+            AlertRule::WeatherFeedDown => "weather_feed_down",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            AlertRule::InverterOffline => "Inverter offline",
+            AlertRule::ZeroProduction => "Zero production during daylight",
+            AlertRule::DailyTotalLow => "Daily total below expected",
+            AlertRule::SemsLoginFailing => "SEMS login failing",
+            AlertRule::UvFeedDown => "UV feed down",
+            AlertRule::WeatherFeedDown => "Weather feed down",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertTransition {
+    Fired(String),
+    Resolved,
+}
+
+#[derive(FromRow)]
+pub struct AlertState {
+    pub breaches: i32,
+    pub firing: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertRules {
+    pub channel_id: Option<Id<ChannelMarker>>,
+    /// Minutes without a new reading before the inverter is considered offline.
+    pub no_data_mins: i32,
+    /// Percentage of the usual total for this time of day below which today is flagged.
+    pub daily_total_percent: f64,
+    /// Consecutive failed polls before an upstream is considered down.
+    pub failure_threshold: i32,
+}
+
+impl Default for AlertRules {
+    fn default() -> Self {
+        Self {
+            channel_id: None,
+            no_data_mins: 10,
+            daily_total_percent: 50.0,
+            failure_threshold: 3,
+        }
+    }
+}
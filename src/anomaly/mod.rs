@@ -0,0 +1,234 @@
+use chrono::NaiveDateTime;
+use sqlx::{PgPool, prelude::FromRow};
+use tracing::{Instrument, instrument};
+use types::{Anomaly, AnomalyChanges, AnomalyKind};
+
+use crate::metrics::Timed;
+
+pub mod types;
+
+#[derive(Clone, Debug)]
+pub struct AnomalyDetector {
+    db: PgPool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AnomalyDetectorError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+#[derive(FromRow)]
+struct WindowStats {
+    avg_wh: Option<f64>,
+    min_wh: Option<f64>,
+    max_wh: Option<f64>,
+    samples: i64,
+}
+
+impl AnomalyDetector {
+    /// Window used when comparing actual output against expected output.
+    const WINDOW_MINS: i32 = 30;
+    /// How long readings must be identical before they count as flat-lined.
+    const FLAT_LINE_MINS: i32 = 20;
+    const FLAT_LINE_MIN_SAMPLES: i64 = 5;
+    /// Number of previous days used to build the expected output.
+    const LOOKBACK_DAYS: i32 = 30;
+    /// Below this fraction of the expected output the array is underperforming.
+    const SHORTFALL_RATIO: f64 = 0.5;
+    /// Expected output above which we consider it to be daylight.
+    const DAYLIGHT_EXPECTED_WH: f64 = 100.0;
+
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Compares the latest readings against what the array normally produces
+    /// at this time of day, opening or resolving anomalies as needed.
+    #[instrument(skip(self))]
+    pub async fn detect(&self) -> Result<AnomalyChanges, AnomalyDetectorError> {
+        let expected = self.expected_wh().await?;
+        let window = self.window_stats(Self::WINDOW_MINS).await?;
+        let flat_window = self.window_stats(Self::FLAT_LINE_MINS).await?;
+
+        let daylight = expected.is_some_and(|e| e >= Self::DAYLIGHT_EXPECTED_WH);
+        let expected_wh = expected.unwrap_or(0.0);
+        let actual_wh = window.avg_wh.unwrap_or(0.0);
+
+        // no readings at all is missing data, which the alerts cover
+        let has_samples = window.samples > 0;
+        let zero_output = daylight && has_samples && window.max_wh == Some(0.0);
+        let underperformance = daylight
+            && has_samples
+            && !zero_output
+            && actual_wh < expected_wh * Self::SHORTFALL_RATIO;
+        let flat_line = flat_window.samples >= Self::FLAT_LINE_MIN_SAMPLES
+            && flat_window.max_wh.is_some_and(|max| max > 0.0)
+            && flat_window.min_wh == flat_window.max_wh;
+
+        let checks = [
+            (
+                AnomalyKind::ZeroOutput,
+                zero_output.then(|| {
+                    format!(
+                        "no output for {} mins, expected around {expected_wh:.0} Wh",
+                        Self::WINDOW_MINS
+                    )
+                }),
+            ),
+            (
+                AnomalyKind::Underperformance,
+                underperformance.then(|| {
+                    format!(
+                        "averaging {actual_wh:.0} Wh over {} mins, expected around {expected_wh:.0} Wh",
+                        Self::WINDOW_MINS
+                    )
+                }),
+            ),
+            (
+                AnomalyKind::FlatLine,
+                flat_line.then(|| {
+                    format!(
+                        "reading stuck at {:.0} Wh for {} mins",
+                        flat_window.max_wh.unwrap_or(0.0),
+                        Self::FLAT_LINE_MINS
+                    )
+                }),
+            ),
+        ];
+
+        let mut changes = AnomalyChanges::default();
+        for (kind, details) in checks {
+            match details {
+                Some(details) => {
+                    if let Some(opened) = self.open(kind, details, expected, window.avg_wh).await? {
+                        changes.opened.push(opened);
+                    }
+                }
+                None => {
+                    if let Some(resolved) = self.resolve(kind).await? {
+                        changes.resolved.push(resolved);
+                    }
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(
+        &self,
+        since: Option<NaiveDateTime>,
+        open_only: bool,
+    ) -> Result<Vec<Anomaly>, AnomalyDetectorError> {
+        let anomalies = sqlx::query_as(
+            r#"SELECT * FROM anomalies
+               WHERE ($1::timestamp IS NULL OR started_at >= $1)
+                 AND (NOT $2 OR resolved_at IS NULL)
+               ORDER BY started_at DESC
+               LIMIT 500"#,
+        )
+        .bind(since)
+        .bind(open_only)
+        .fetch_all(&self.db)
+        .timed("anomaly_list")
+        .await?;
+
+        Ok(anomalies)
+    }
+
+    /// The output of a clear day at this time of day, taken as the 90th
+    /// percentile of the same window over the previous days.
+    async fn expected_wh(&self) -> Result<Option<f64>, AnomalyDetectorError> {
+        #[derive(FromRow)]
+        struct Row {
+            expected_wh: Option<f64>,
+        }
+
+        let row: Row = sqlx::query_as(
+            r#"SELECT percentile_cont(0.9) WITHIN GROUP (ORDER BY day_avg) AS expected_wh
+               FROM (
+                   SELECT avg(current_kwh) AS day_avg
+                   FROM solar_data_tsdb
+                   WHERE time > NOW() - MAKE_INTERVAL(days => $1)
+                     AND (time + '8 hour')::date < (NOW() + '8 hour')::date
+                     AND (time + '8 hour')::time BETWEEN ((NOW() + '8 hour') - MAKE_INTERVAL(mins => $2))::time
+                                                     AND (NOW() + '8 hour')::time
+                   GROUP BY (time + '8 hour')::date
+               ) days"#,
+        )
+        .bind(Self::LOOKBACK_DAYS)
+        .bind(Self::WINDOW_MINS)
+        .fetch_one(&self.db)
+        .instrument(tracing::info_span!("anomaly_expected_wh"))
+        .timed("anomaly_expected_wh")
+        .await?;
+
+        Ok(row.expected_wh)
+    }
+
+    async fn window_stats(&self, mins: i32) -> Result<WindowStats, AnomalyDetectorError> {
+        let stats = sqlx::query_as(
+            r#"SELECT avg(current_kwh) AS avg_wh, min(current_kwh) AS min_wh, max(current_kwh) AS max_wh, count(*) AS samples
+               FROM solar_data_tsdb
+               WHERE time > NOW() - MAKE_INTERVAL(mins => $1)"#,
+        )
+        .bind(mins)
+        .fetch_one(&self.db)
+        .instrument(tracing::info_span!("anomaly_window_stats", time_in_mins = mins))
+        .timed("anomaly_window_stats")
+        .await?;
+
+        Ok(stats)
+    }
+
+    /// Records a firing anomaly, returning it only if it was newly opened.
+    async fn open(
+        &self,
+        kind: AnomalyKind,
+        details: String,
+        expected_wh: Option<f64>,
+        actual_wh: Option<f64>,
+    ) -> Result<Option<Anomaly>, AnomalyDetectorError> {
+        let updated = sqlx::query(
+            "UPDATE anomalies SET last_seen_at = now(), details = $2, expected_wh = $3, actual_wh = $4 WHERE kind = $1 AND resolved_at IS NULL",
+        )
+        .bind(kind.as_str())
+        .bind(&details)
+        .bind(expected_wh)
+        .bind(actual_wh)
+        .execute(&self.db)
+        .timed("anomaly_update")
+        .await?;
+
+        if updated.rows_affected() > 0 {
+            return Ok(None);
+        }
+
+        let anomaly = sqlx::query_as(
+            "INSERT INTO anomalies (kind, details, expected_wh, actual_wh) VALUES ($1, $2, $3, $4) RETURNING *",
+        )
+        .bind(kind.as_str())
+        .bind(details)
+        .bind(expected_wh)
+        .bind(actual_wh)
+        .fetch_one(&self.db)
+        .timed("anomaly_insert")
+        .await?;
+
+        Ok(Some(anomaly))
+    }
+
+    async fn resolve(&self, kind: AnomalyKind) -> Result<Option<Anomaly>, AnomalyDetectorError> {
+        let anomaly = sqlx::query_as(
+            "UPDATE anomalies SET resolved_at = now() WHERE kind = $1 AND resolved_at IS NULL RETURNING *",
+        )
+        .bind(kind.as_str())
+        .fetch_optional(&self.db)
+        .timed("anomaly_resolve")
+        .await?;
+
+        Ok(anomaly)
+    }
+}
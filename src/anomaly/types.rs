@@ -0,0 +1,39 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    Underperformance,
+    ZeroOutput,
+    FlatLine,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::Underperformance => "underperformance",
+            AnomalyKind::ZeroOutput => "zero_output",
+            AnomalyKind::FlatLine => "flat_line",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Anomaly {
+    pub id: i32,
+    pub kind: String,
+    pub details: String,
+    pub expected_wh: Option<f64>,
+    pub actual_wh: Option<f64>,
+    pub started_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub resolved_at: Option<NaiveDateTime>,
+}
+
+#[derive(Default, Debug)]
+pub struct AnomalyChanges {
+    pub opened: Vec<Anomaly>,
+    pub resolved: Vec<Anomaly>,
+}
@@ -1,39 +1,141 @@
 use crate::{
-    get_average_for_last_n_minutes,
-    goodwe::{self, GoodWeSemsAPI},
-    tracing_setup::TimeTrace,
+    alerts::AlertManager,
+    anomaly::{AnomalyDetector, AnomalyDetectorError},
+    background::schedule::PollSchedule,
+    config::types::{HomeGatewayConfig, PollerConfig},
+    goodwe::{self, GoodWeSemsAPI, types::PlantDetailsByPowerStationIdResponse},
+    metrics::{Timed, Upstream, metrics},
+    mqtt::{MqttPublisher, types::Reading},
+    outbox::{HOME_GATEWAY, Outbox, OutboxError},
     weather::{self, WeatherAPI},
+    webhooks::{
+        WebhookError, Webhooks,
+        types::{AnomalyEvent, WebhookEvent},
+    },
 };
+use chrono::{DateTime, Utc};
 use futures::FutureExt;
-use reqwest_tracing::TracingMiddleware;
-use serde::Serialize;
-use sqlx::PgPool;
-use std::panic::AssertUnwindSafe;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, prelude::FromRow};
+use std::{
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex, MutexGuard};
 use tracing::instrument;
 
+pub mod schedule;
+
 #[derive(Clone)]
 pub struct BackgroundTask {
     pool: PgPool,
     solar_api: GoodWeSemsAPI,
     weather_api: WeatherAPI,
-    http_client: reqwest_middleware::ClientWithMiddleware,
+    anomaly_detector: AnomalyDetector,
+    alert_manager: AlertManager,
+    home_gateway: HomeGatewayConfig,
+    mqtt: Option<MqttPublisher>,
+    webhooks: Webhooks,
+    poller: PollerConfig,
+    schedule: PollSchedule,
+    /// When the last poll started, for night-time throttling.
+    last_poll: Arc<std::sync::Mutex<Option<DateTime<Utc>>>>,
+    /// Held for the duration of a poll so shutdown can wait for it to finish.
+    in_flight: Arc<Mutex<()>>,
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum BackgroundTaskError {
     #[error("a http error occurred: {0}")]
-    SolarAPI(#[from] goodwe::GoodWeSemsAPIError),
+    SolarAPI(#[from] FetchError<goodwe::GoodWeSemsAPIError>),
     #[error("unknown error occurred: {0}")]
     WeatherAPI(#[from] weather::WeatherAPIError),
     #[error("a database error occurred: {0}")]
     Database(#[from] sqlx::Error),
-    #[error("a http error occurred: {0}")]
-    Http(#[from] reqwest_middleware::Error),
+    #[error("an outbox error occurred: {0}")]
+    Outbox(#[from] OutboxError),
+    #[error("a webhook error occurred: {0}")]
+    Webhook(#[from] WebhookError),
+    #[error("a serialisation error occurred: {0}")]
+    Serialize(#[from] serde_json::Error),
     #[error("unknown error occurred: {0}")]
     Unknown(#[from] anyhow::Error),
 }
 
-#[derive(Serialize)]
+#[derive(thiserror::Error, Debug)]
+pub enum FetchError<E> {
+    #[error(transparent)]
+    Upstream(E),
+    #[error("timed out after {}s", .0.as_secs())]
+    Timeout(Duration),
+}
+
+/// Outcome of the individual upstream calls made during a poll.
+#[derive(Default, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunReport {
+    pub current_kwh: Option<f64>,
+    pub error: Option<String>,
+    pub login_error: Option<String>,
+    pub uv_error: Option<String>,
+    pub weather_error: Option<String>,
+    pub steps: Vec<RunStep>,
+}
+
+impl RunReport {
+    fn step<T, E: std::fmt::Display>(
+        &mut self,
+        name: &str,
+        elapsed: Duration,
+        result: &Result<T, E>,
+    ) {
+        self.steps.push(RunStep {
+            name: name.to_string(),
+            duration_ms: elapsed.as_millis() as u64,
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+    }
+
+    pub fn outcome(&self) -> JobOutcome {
+        if self.error.is_some() {
+            JobOutcome::Failure
+        } else if self.steps.iter().any(|step| step.error.is_some()) {
+            JobOutcome::Partial
+        } else {
+            JobOutcome::Success
+        }
+    }
+}
+
+/// Timing and result of one stage of a poll.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunStep {
+    pub name: String,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobOutcome {
+    Success,
+    /// The reading was stored but a later step or secondary feed failed.
+    Partial,
+    Failure,
+}
+
+impl JobOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobOutcome::Success => "success",
+            JobOutcome::Partial => "partial",
+            JobOutcome::Failure => "failure",
+        }
+    }
+}
+
+#[derive(Serialize, FromRow)]
 pub struct SolarIngestAvgPayload {
     pub mins_15: Option<f64>,
     pub mins_60: Option<f64>,
@@ -48,96 +150,315 @@ pub struct SolarIngestPayload {
 }
 
 impl BackgroundTask {
-    pub fn new(pool: PgPool, solar_api: GoodWeSemsAPI, weather_api: WeatherAPI) -> Self {
+    /// Name recorded in `job_runs`.
+    pub const JOB: &str = "ingest";
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        pool: PgPool,
+        solar_api: GoodWeSemsAPI,
+        weather_api: WeatherAPI,
+        anomaly_detector: AnomalyDetector,
+        alert_manager: AlertManager,
+        home_gateway: HomeGatewayConfig,
+        mqtt: Option<MqttPublisher>,
+        webhooks: Webhooks,
+        poller: PollerConfig,
+    ) -> Self {
         Self {
             pool,
             solar_api,
             weather_api,
-            http_client: reqwest_middleware::ClientBuilder::new(
-                reqwest::ClientBuilder::new().build().unwrap(),
-            )
-            .with(TracingMiddleware::<TimeTrace>::new())
-            .build(),
+            anomaly_detector,
+            alert_manager,
+            home_gateway,
+            mqtt,
+            webhooks,
+            schedule: PollSchedule::new(&poller),
+            poller,
+            last_poll: Arc::default(),
+            in_flight: Arc::new(Mutex::new(())),
         }
     }
 
-    #[instrument(name = "BackgroundTask::run_task", skip(self), fields(otel.kind = "internal"))]
-    pub async fn run_task(&self) {
-        let fut = async move {
-            tracing::info!("fetching data");
-            let login_data = self.solar_api.get_new_or_cached_login_data().await?;
-            let solar_data = self.solar_api.get_solar_data(login_data).await?;
+    /// Waits for any in-progress poll to complete.
+    pub async fn wait_idle(&self) {
+        let _guard = self.in_flight.lock().await;
+    }
 
-            let kwh = solar_data.data.kpi.pac;
-            let raw_data = serde_json::to_value(&solar_data).unwrap();
+    /// Polls for a scheduler tick, unless the tick falls between the spaced
+    /// out polls made overnight.
+    /// Skips the tick if the previous poll is still running.
+    pub async fn run_scheduled(&self) -> Option<RunReport> {
+        let last_poll = *self.last_poll.lock().unwrap();
+        if !self.schedule.is_due(Utc::now(), last_poll) {
+            tracing::debug!("throttled overnight, skipping poll");
+            return None;
+        }
 
-            tracing::info!("fetched solar data: {kwh}");
+        let Ok(guard) = self.in_flight.try_lock() else {
+            tracing::warn!("previous poll still in flight, skipping poll");
+            return None;
+        };
+
+        Some(self.run(guard).await)
+    }
+
+    /// Polls now, waiting for any poll already in flight.
+    pub async fn run_task(&self) -> RunReport {
+        let guard = self.in_flight.lock().await;
+        self.run(guard).await
+    }
 
-            let uv_level = self.weather_api.get_uv_level(WeatherAPI::PERTH_NAME).await;
+    #[instrument(name = "BackgroundTask::run", skip_all, fields(otel.kind = "internal"))]
+    async fn run(&self, _guard: MutexGuard<'_, ()>) -> RunReport {
+        let started_at = Utc::now();
+        *self.last_poll.lock().unwrap() = Some(started_at);
+        let mut report = RunReport::default();
 
-            if let Err(ref e) = uv_level {
-                tracing::error!("error getting uv level: {e}");
+        match AssertUnwindSafe(self.ingest(&mut report))
+            .catch_unwind()
+            .await
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                tracing::error!("error fetching data: {e}");
+                report.error = Some(e.to_string());
             }
+            Err(e) => {
+                tracing::error!("panic fetching data: {e:?}");
+                report.error = Some("panic fetching data".to_string());
+            }
+        }
+
+        let started = Instant::now();
+        let evaluated = self.alert_manager.evaluate(&report).await;
+        report.step("alerts", started.elapsed(), &evaluated);
+
+        if let Err(e) = evaluated {
+            tracing::error!("error evaluating alerts: {e}");
+        }
 
-            let uv_level = uv_level.ok();
-            tracing::info!("fetched uv level: {uv_level:?}");
+        self.record_run(started_at, &report).await;
 
-            let weather_details = self
-                .weather_api
-                .get_weather_details(WeatherAPI::JANDAKOT_GEOCODE)
-                .await;
+        report
+    }
+
+    async fn fetch_solar_data(
+        &self,
+        report: &mut RunReport,
+    ) -> Result<PlantDetailsByPowerStationIdResponse, goodwe::GoodWeSemsAPIError> {
+        let login_data = self
+            .solar_api
+            .get_new_or_cached_login_data()
+            .await
+            .inspect_err(|e| report.login_error = Some(e.to_string()))?;
 
-            if let Err(ref e) = weather_details {
-                tracing::error!("error getting weather details: {e}");
+        self.solar_api.get_solar_data(login_data).await
+    }
+
+    /// Runs an upstream call under its own time limit, recording its metrics.
+    async fn fetch<T, E>(
+        upstream: Upstream,
+        limit: Duration,
+        fetch: impl Future<Output = Result<T, E>>,
+    ) -> (Result<T, FetchError<E>>, Duration) {
+        let started = Instant::now();
+        let result = match tokio::time::timeout(limit, fetch).await {
+            Ok(result) => result.map_err(FetchError::Upstream),
+            Err(_) => Err(FetchError::Timeout(limit)),
+        };
+        let elapsed = started.elapsed();
+        metrics().record_upstream(upstream, elapsed, &result);
+
+        (result, elapsed)
+    }
+
+    async fn ingest(&self, report: &mut RunReport) -> Result<(), BackgroundTaskError> {
+        tracing::info!("fetching data");
+        let poller = &self.poller;
+        // the upstreams are independent, so a slow one only holds up itself
+        let (sems, uv, bom) = tokio::join!(
+            Self::fetch(
+                Upstream::Sems,
+                Duration::from_secs(poller.sems_timeout_secs),
+                self.fetch_solar_data(report),
+            ),
+            Self::fetch(
+                Upstream::Arpansa,
+                Duration::from_secs(poller.uv_timeout_secs),
+                self.weather_api.get_uv_level(WeatherAPI::PERTH_NAME),
+            ),
+            Self::fetch(
+                Upstream::Bom,
+                Duration::from_secs(poller.bom_timeout_secs),
+                self.weather_api
+                    .get_weather_details(WeatherAPI::JANDAKOT_GEOCODE),
+            ),
+        );
+        let (solar_data, sems_elapsed) = sems;
+        let (uv_level, uv_elapsed) = uv;
+        let (weather_details, bom_elapsed) = bom;
+        report.step(Upstream::Sems.as_str(), sems_elapsed, &solar_data);
+        report.step(Upstream::Arpansa.as_str(), uv_elapsed, &uv_level);
+        report.step(Upstream::Bom.as_str(), bom_elapsed, &weather_details);
+
+        if let Err(ref e) = uv_level {
+            tracing::error!("error getting uv level: {e}");
+            report.uv_error = Some(e.to_string());
+        }
+
+        if let Err(ref e) = weather_details {
+            tracing::error!("error getting weather details: {e}");
+            report.weather_error = Some(e.to_string());
+        }
+
+        let solar_data = solar_data?;
+
+        let kwh = solar_data.data.kpi.pac;
+        let raw_data = serde_json::to_value(&solar_data)?;
+
+        tracing::info!("fetched solar data: {kwh}");
+        report.current_kwh = Some(kwh);
+        metrics().record_reading(kwh, solar_data.data.kpi.power);
+
+        let uv_level = uv_level.ok();
+        tracing::info!("fetched uv level: {uv_level:?}");
+
+        let current_temperature = weather_details.ok().map(|w| w.data.temp);
+        tracing::info!("fetched weather details: {current_temperature:?}");
+
+        let reading = Reading {
+            power_w: kwh,
+            energy_today_kwh: solar_data.data.kpi.power,
+            energy_lifetime_kwh: solar_data.data.kpi.total_power,
+            uv_level,
+            temperature: current_temperature,
+        };
+
+        let started = Instant::now();
+        let stored = self.store(&reading, raw_data).await;
+        report.step("store", started.elapsed(), &stored);
+        stored?;
+
+        if let Some(mqtt) = &self.mqtt {
+            let started = Instant::now();
+            let published = mqtt.publish(&reading);
+            report.step("mqtt", started.elapsed(), &published);
+
+            if let Err(e) = published {
+                tracing::error!("error publishing to mqtt: {e}");
             }
+        }
 
-            let current_temperature = weather_details.ok().map(|w| w.data.temp);
-            tracing::info!("fetched weather details: {current_temperature:?}");
+        let started = Instant::now();
+        let detected = self.detect_anomalies().await;
+        report.step("anomalies", started.elapsed(), &detected);
 
-            sqlx::query!(
-                "INSERT INTO solar_data_tsdb (current_kwh, raw_data, uv_level, temperature) VALUES ($1, $2, $3, $4)",
-                kwh,
-                raw_data,
-                uv_level,
-                current_temperature
+        if let Err(e) = detected {
+            tracing::error!("error detecting anomalies: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Stores the reading and queues its outgoing pushes in one transaction.
+    async fn store(
+        &self,
+        reading: &Reading,
+        raw_data: serde_json::Value,
+    ) -> Result<(), BackgroundTaskError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "INSERT INTO solar_data_tsdb (current_kwh, raw_data, uv_level, temperature, station_id) VALUES ($1, $2, $3, $4, $5)",
+            reading.power_w,
+            raw_data,
+            reading.uv_level,
+            reading.temperature,
+            self.solar_api.powerstation_id()
+        )
+        .execute(&mut *tx)
+        .timed("insert_reading")
+        .await?;
+
+        if self.home_gateway.base_url.is_some() {
+            let averages: SolarIngestAvgPayload = sqlx::query_as(
+                r#"SELECT avg(current_kwh) FILTER (WHERE time > now() - interval '15 minutes') AS mins_15,
+                          avg(current_kwh) FILTER (WHERE time > now() - interval '60 minutes') AS mins_60,
+                          avg(current_kwh) AS mins_180
+                   FROM solar_data_tsdb
+                   WHERE time > now() - interval '180 minutes'"#,
             )
-            .execute(&self.pool)
+            .fetch_one(&mut *tx)
+            .timed("home_gateway_averages")
             .await?;
 
-            if let Some(home_gateway_api_base) = std::env::var("HOME_GATEWAY_BASE_URL").ok() {
-                let url = format!("{home_gateway_api_base}/v1/ingest/solar");
-                let api_key =
-                    std::env::var("HOME_GATEWAY_API_KEY").unwrap_or_else(|_| "".to_owned());
-
-                // FIXME: expensive
-                let avg_15_mins = get_average_for_last_n_minutes(15, &self.solar_api).await?;
-                let avg_1_hour = get_average_for_last_n_minutes(60, &self.solar_api).await?;
-                let avg_3_hours = get_average_for_last_n_minutes(180, &self.solar_api).await?;
-
-                let response = self
-                    .http_client
-                    .post(url)
-                    .header("X-Api-Key", api_key)
-                    .json(&SolarIngestPayload {
-                        current_kwh: kwh,
-                        average_kwh: SolarIngestAvgPayload {
-                            mins_15: avg_15_mins,
-                            mins_60: avg_1_hour,
-                            mins_180: avg_3_hours,
-                        },
-                        uv_level,
-                    })
-                    .send()
-                    .await?;
-
-                tracing::info!("home-gateway response: {}", response.status());
+            let payload = SolarIngestPayload {
+                current_kwh: reading.power_w,
+                average_kwh: averages,
+                uv_level: reading.uv_level,
             };
+            Outbox::enqueue(&mut tx, HOME_GATEWAY, &payload).await?;
+        }
 
-            Ok::<(), BackgroundTaskError>(())
-        };
+        self.webhooks
+            .enqueue(&mut tx, WebhookEvent::Sample, reading)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn detect_anomalies(&self) -> Result<(), AnomalyDetectorError> {
+        let changes = self.anomaly_detector.detect().await?;
+
+        for anomaly in &changes.opened {
+            tracing::warn!("anomaly detected: {} - {}", anomaly.kind, anomaly.details);
+        }
+
+        for anomaly in &changes.resolved {
+            tracing::info!("anomaly resolved: {}", anomaly.kind);
+        }
+
+        let events = changes
+            .opened
+            .iter()
+            .map(|anomaly| AnomalyEvent::new("opened", anomaly))
+            .chain(
+                changes
+                    .resolved
+                    .iter()
+                    .map(|anomaly| AnomalyEvent::new("resolved", anomaly)),
+            );
+        for event in events {
+            if let Err(e) = self.webhooks.emit(WebhookEvent::Anomaly, &event).await {
+                tracing::error!("error queueing anomaly webhooks: {e}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records the run in `job_runs` for the health check and later inspection.
+    async fn record_run(&self, started_at: DateTime<Utc>, report: &RunReport) {
+        let result = sqlx::query(
+            r#"INSERT INTO job_runs (job, started_at, finished_at, outcome, steps, error)
+               VALUES ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(Self::JOB)
+        .bind(started_at.naive_utc())
+        .bind(Utc::now().naive_utc())
+        .bind(report.outcome().as_str())
+        .bind(sqlx::types::Json(&report.steps))
+        .bind(&report.error)
+        .execute(&self.pool)
+        .timed("record_job_run")
+        .await;
 
-        if let Err(e) = AssertUnwindSafe(fut).catch_unwind().await {
-            tracing::error!("error fetching data: {e:?}");
+        if let Err(e) = result {
+            tracing::error!("error recording job run: {e}");
         }
     }
 }
@@ -0,0 +1,121 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    config::types::PollerConfig,
+    sun::{self, SITE},
+};
+
+/// Decides which scheduler ticks poll, throttling polls overnight when the
+/// panels produce nothing.
+#[derive(Debug, Clone)]
+pub struct PollSchedule {
+    night_interval: Duration,
+    twilight: Duration,
+}
+
+impl PollSchedule {
+    pub fn new(config: &PollerConfig) -> Self {
+        Self {
+            night_interval: Duration::minutes(config.night_interval_mins),
+            twilight: Duration::minutes(config.twilight_mins),
+        }
+    }
+
+    /// Whether `at` is outside the window from `twilight` before sunrise to
+    /// `twilight` after sunset at the site.
+    pub fn is_night(&self, at: DateTime<Utc>) -> bool {
+        let date = at.with_timezone(&chrono_tz::Australia::Perth).date_naive();
+
+        match sun::sun_times(date, &SITE) {
+            Some(sun_times) => {
+                at < sun_times.sunrise - self.twilight || at > sun_times.sunset + self.twilight
+            }
+            None => false,
+        }
+    }
+
+    /// The least time between polls at `at`. Zero polls on every tick.
+    pub fn interval(&self, at: DateTime<Utc>) -> Duration {
+        if self.is_night(at) {
+            self.night_interval
+        } else {
+            Duration::zero()
+        }
+    }
+
+    pub fn is_due(&self, now: DateTime<Utc>, last_poll: Option<DateTime<Utc>>) -> bool {
+        // ticks land a little late, so allow a minute of slack
+        last_poll.is_none_or(|last| now - last >= self.interval(now) - Duration::minutes(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime, TimeZone};
+
+    use super::*;
+
+    fn perth(date: (i32, u32, u32), hour: u32, min: u32) -> DateTime<Utc> {
+        let date = NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap();
+        chrono_tz::Australia::Perth
+            .from_local_datetime(&date.and_time(NaiveTime::from_hms_opt(hour, min, 0).unwrap()))
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn schedule(night_interval_mins: i64) -> PollSchedule {
+        PollSchedule::new(&PollerConfig {
+            night_interval_mins,
+            twilight_mins: 30,
+            ..PollerConfig::default()
+        })
+    }
+
+    #[test]
+    fn skips_ticks_at_night() {
+        let schedule = schedule(30);
+        let now = perth((2026, 6, 21), 1, 0);
+
+        assert!(schedule.is_night(now));
+        assert!(!schedule.is_due(now, Some(now - Duration::minutes(10))));
+        // a tick that lands a little early still counts
+        assert!(schedule.is_due(now, Some(now - Duration::minutes(29))));
+        assert!(schedule.is_due(now, None));
+    }
+
+    #[test]
+    fn night_wraps_around_midnight() {
+        let schedule = schedule(30);
+
+        for now in [perth((2026, 6, 21), 23, 50), perth((2026, 6, 22), 0, 10)] {
+            assert!(schedule.is_night(now), "{now}");
+        }
+    }
+
+    #[test]
+    fn polls_every_tick_in_the_twilight_window() {
+        let schedule = schedule(30);
+        let sun_times =
+            sun::sun_times(NaiveDate::from_ymd_opt(2026, 6, 21).unwrap(), &SITE).unwrap();
+
+        for now in [
+            sun_times.sunrise - Duration::minutes(20),
+            sun_times.sunset + Duration::minutes(20),
+        ] {
+            assert!(!schedule.is_night(now), "{now}");
+            assert!(schedule.is_due(now, Some(now - Duration::minutes(1))));
+        }
+
+        let after_dusk = sun_times.sunset + Duration::minutes(40);
+        assert!(schedule.is_night(after_dusk));
+        assert!(!schedule.is_due(after_dusk, Some(after_dusk - Duration::minutes(1))));
+    }
+
+    #[test]
+    fn zero_interval_polls_every_tick() {
+        let schedule = schedule(0);
+        let now = perth((2026, 6, 21), 1, 0);
+
+        assert!(schedule.is_due(now, Some(now - Duration::minutes(1))));
+    }
+}
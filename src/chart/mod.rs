@@ -0,0 +1,175 @@
+use std::sync::OnceLock;
+
+use chrono::{Duration, Timelike};
+use plotters::{
+    prelude::*,
+    style::{FontStyle, register_font},
+};
+
+use crate::types::GenerationHistory;
+
+const WIDTH: u32 = 1000;
+const HEIGHT: u32 = 500;
+const FONT: &str = "sans-serif";
+// UV and temperature share the secondary axis
+const SECONDARY_MAX: f64 = 45.0;
+
+pub const TODAY_COLOUR: RGBColor = RGBColor(0xe7, 0x6e, 0x50);
+pub const COMPARE_COLOUR: RGBColor = RGBColor(0x19, 0x76, 0xd2);
+const UV_LEVEL_COLOUR: RGBColor = RGBColor(0x43, 0xa0, 0x47);
+const TEMPERATURE_COLOUR: RGBColor = RGBColor(0xf9, 0xa8, 0x25);
+
+#[derive(thiserror::Error, Debug)]
+pub enum ChartError {
+    #[error("a drawing error occurred: {0}")]
+    Drawing(String),
+    #[error("a png error occurred: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error("failed to load chart font")]
+    Font,
+}
+
+pub struct HistorySeries<'a> {
+    pub label: String,
+    pub history: &'a [GenerationHistory],
+    pub colour: RGBColor,
+    /// Whether to draw the UV and temperature overlays for this series.
+    pub overlays: bool,
+}
+
+fn drawing<E: std::error::Error + Send + Sync>(e: DrawingAreaErrorKind<E>) -> ChartError {
+    ChartError::Drawing(e.to_string())
+}
+
+fn register_fonts() -> Result<(), ChartError> {
+    static REGISTERED: OnceLock<bool> = OnceLock::new();
+
+    let registered = REGISTERED.get_or_init(|| {
+        register_font(
+            FONT,
+            FontStyle::Normal,
+            include_bytes!("../../assets/DejaVuSans.ttf"),
+        )
+        .is_ok()
+    });
+
+    if *registered {
+        Ok(())
+    } else {
+        Err(ChartError::Font)
+    }
+}
+
+/// Hours since local midnight, so different days line up on the same axis.
+fn local_hours(history: &GenerationHistory) -> f64 {
+    let local = history.at + Duration::hours(8);
+    local.hour() as f64 + local.minute() as f64 / 60.0
+}
+
+/// Renders a PNG line chart of power over the day for each series.
+pub fn render_history(title: &str, series: &[HistorySeries<'_>]) -> Result<Vec<u8>, ChartError> {
+    register_fonts()?;
+
+    let max_wh = series
+        .iter()
+        .flat_map(|s| s.history)
+        .map(|h| h.wh)
+        .fold(0f64, f64::max);
+    let y_max = ((max_wh / 1000.0).ceil() * 1000.0).max(1000.0);
+
+    let mut buffer = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE).map_err(drawing)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, (FONT, 24))
+            .margin(16)
+            .x_label_area_size(32)
+            .y_label_area_size(56)
+            .right_y_label_area_size(48)
+            .build_cartesian_2d(0f64..24f64, 0f64..y_max)
+            .map_err(drawing)?
+            .set_secondary_coord(0f64..24f64, 0f64..SECONDARY_MAX);
+
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .light_line_style(WHITE)
+            .bold_line_style(RGBColor(0xcc, 0xcc, 0xcc).stroke_width(1))
+            .x_labels(13)
+            .x_label_formatter(&|h| format!("{:02}:00", *h as u32))
+            .y_label_formatter(&|wh| format!("{wh:.0}"))
+            .y_desc("Wh")
+            .label_style((FONT, 14))
+            .draw()
+            .map_err(drawing)?;
+
+        chart
+            .configure_secondary_axes()
+            .y_label_formatter(&|v| format!("{v:.0}"))
+            .y_desc("UV / °C")
+            .label_style((FONT, 14))
+            .draw()
+            .map_err(drawing)?;
+
+        for s in series {
+            let colour = s.colour;
+            chart
+                .draw_series(LineSeries::new(
+                    s.history.iter().map(|h| (local_hours(h), h.wh)),
+                    colour.stroke_width(2),
+                ))
+                .map_err(drawing)?
+                .label(&s.label)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], colour));
+
+            if !s.overlays {
+                continue;
+            }
+
+            chart
+                .draw_secondary_series(DashedLineSeries::new(
+                    s.history
+                        .iter()
+                        .filter_map(|h| h.uv_level.map(|uv| (local_hours(h), uv))),
+                    4,
+                    4,
+                    UV_LEVEL_COLOUR.into(),
+                ))
+                .map_err(drawing)?
+                .label("UV Level")
+                .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], UV_LEVEL_COLOUR));
+
+            chart
+                .draw_secondary_series(LineSeries::new(
+                    s.history
+                        .iter()
+                        .filter_map(|h| h.temperature.map(|t| (local_hours(h), t))),
+                    TEMPERATURE_COLOUR,
+                ))
+                .map_err(drawing)?
+                .label("Temperature")
+                .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], TEMPERATURE_COLOUR));
+        }
+
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperLeft)
+            .label_font((FONT, 14))
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()
+            .map_err(drawing)?;
+
+        root.present().map_err(drawing)?;
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&buffer)?;
+
+    Ok(png)
+}
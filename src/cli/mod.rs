@@ -0,0 +1,353 @@
+use std::{
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use futures::TryStreamExt;
+use sqlx::{PgPool, prelude::FromRow};
+
+use crate::{
+    background::BackgroundTask, goodwe::GoodWeSemsAPI, influx::InfluxSink, quality::DataQuality,
+    summary::SummaryService, weather::WeatherAPI, webhooks::Webhooks,
+};
+
+#[derive(Parser)]
+#[command(version, about = "Solar panel monitoring service")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Default)]
+pub enum Command {
+    /// Run the poller, HTTP API and Discord bot (the default).
+    #[default]
+    Serve,
+    /// Run a single ingest cycle and print the result.
+    PollOnce,
+    /// Apply pending database migrations and exit.
+    Migrate,
+    /// Fill in readings for past days from the SEMS power chart.
+    Backfill {
+        /// First local date to fetch.
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last local date to fetch, inclusive. Must be before today.
+        #[arg(long)]
+        to: NaiveDate,
+    },
+    /// Write stored readings to stdout or a file.
+    Export {
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Log in to SEMS and fetch each weather feed, reporting what works.
+    CheckCredentials,
+    /// Rebuild the daily summary rollups from stored readings.
+    RecomputeRollups {
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+    /// Report gaps and implausible values in stored readings.
+    DataQuality {
+        /// First local date to scan. Defaults to the last 24 hours.
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// Last local date to scan, inclusive.
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// Refill past days containing gaps from the SEMS power chart first.
+        #[arg(long)]
+        backfill: bool,
+    },
+    /// Write every stored reading to InfluxDB.
+    InfluxReplay,
+    /// Show the most recent webhook delivery attempts.
+    WebhookDeliveries {
+        /// Only show deliveries to this webhook.
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+}
+
+#[derive(FromRow)]
+struct ExportRow {
+    time: NaiveDateTime,
+    station_id: Option<String>,
+    current_kwh: f64,
+    uv_level: Option<f64>,
+    temperature: Option<f64>,
+}
+
+pub async fn migrate(pool: &PgPool) -> anyhow::Result<()> {
+    sqlx::migrate!("./migrations").run(pool).await?;
+    println!("migrations applied");
+
+    Ok(())
+}
+
+pub async fn poll_once(task: &BackgroundTask) -> anyhow::Result<()> {
+    let report = task.run_task().await;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    match report.error {
+        Some(e) => anyhow::bail!("poll failed: {e}"),
+        None => Ok(()),
+    }
+}
+
+/// Inserts readings from the SEMS power chart for each day in the range,
+/// skipping any point with a reading within 150 seconds of it.
+pub async fn backfill(
+    solar_api: &GoodWeSemsAPI,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> anyhow::Result<()> {
+    let today = Utc::now()
+        .with_timezone(&chrono_tz::Australia::Perth)
+        .date_naive();
+    if from > to {
+        anyhow::bail!("--from must not be after --to");
+    }
+    if to >= today {
+        anyhow::bail!("--to must be before today ({today})");
+    }
+
+    let login = solar_api.get_new_or_cached_login_data().await?;
+
+    for date in from.iter_days().take_while(|date| *date <= to) {
+        let inserted = solar_api.backfill_date(login.clone(), date).await?;
+        println!("{date}: inserted {inserted} readings");
+    }
+
+    Ok(())
+}
+
+pub async fn export(
+    pool: &PgPool,
+    format: ExportFormat,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    output: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let mut writer: BufWriter<Box<dyn Write>> = BufWriter::new(match output {
+        Some(path) => Box::new(
+            std::fs::File::create(&path)
+                .with_context(|| format!("could not create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    });
+
+    let mut rows = sqlx::query_as::<_, ExportRow>(
+        r#"SELECT time, station_id, current_kwh, uv_level, temperature
+           FROM solar_data_tsdb
+           WHERE ($1::date IS NULL OR (time + '8 hour')::date >= $1)
+             AND ($2::date IS NULL OR (time + '8 hour')::date <= $2)
+           ORDER BY time"#,
+    )
+    .bind(from)
+    .bind(to)
+    .fetch(pool);
+
+    match format {
+        ExportFormat::Csv => {
+            writeln!(writer, "time,station_id,current_kwh,uv_level,temperature")?;
+
+            while let Some(row) = rows.try_next().await? {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{}",
+                    row.time.and_utc().to_rfc3339(),
+                    csv_field(row.station_id.as_deref().unwrap_or_default()),
+                    row.current_kwh,
+                    row.uv_level.map(|v| v.to_string()).unwrap_or_default(),
+                    row.temperature.map(|v| v.to_string()).unwrap_or_default(),
+                )?;
+            }
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub async fn check_credentials(
+    solar_api: &GoodWeSemsAPI,
+    weather_api: &WeatherAPI,
+) -> anyhow::Result<()> {
+    let mut checks: Vec<(&str, Result<String, String>)> = vec![];
+
+    match solar_api.login().await {
+        Ok(login) if login.has_error => checks.push(("SEMS login", Err(login.msg))),
+        Ok(login) => {
+            checks.push(("SEMS login", Ok("logged in".to_string())));
+            checks.push((
+                "SEMS power station",
+                solar_api
+                    .get_solar_data(login.data)
+                    .await
+                    .map(|data| format!("current output {} W", data.data.kpi.pac))
+                    .map_err(|e| e.to_string()),
+            ));
+        }
+        Err(e) => checks.push(("SEMS login", Err(e.to_string()))),
+    }
+
+    checks.push((
+        "UV feed",
+        weather_api
+            .get_uv_level(WeatherAPI::PERTH_NAME)
+            .await
+            .map(|uv| format!("UV {uv}"))
+            .map_err(|e| e.to_string()),
+    ));
+    checks.push((
+        "Weather observations",
+        weather_api
+            .get_weather_details(WeatherAPI::JANDAKOT_GEOCODE)
+            .await
+            .map(|details| format!("{}°C", details.data.temp))
+            .map_err(|e| e.to_string()),
+    ));
+    checks.push((
+        "Weather forecast",
+        weather_api
+            .get_daily_forecast(WeatherAPI::JANDAKOT_GEOCODE)
+            .await
+            .map(|_| "fetched".to_string())
+            .map_err(|e| e.to_string()),
+    ));
+
+    let mut failed = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(detail) => println!("ok    {name}: {detail}"),
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {name}: {e}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} checks failed", checks.len());
+    }
+
+    Ok(())
+}
+
+pub async fn recompute_rollups(
+    pool: &PgPool,
+    summary: &SummaryService,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> anyhow::Result<()> {
+    let dates: Vec<NaiveDate> = sqlx::query_scalar(
+        r#"SELECT DISTINCT (time + '8 hour')::date
+           FROM solar_data_tsdb
+           WHERE ($1::date IS NULL OR (time + '8 hour')::date >= $1)
+             AND ($2::date IS NULL OR (time + '8 hour')::date <= $2)
+           ORDER BY 1"#,
+    )
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+
+    for date in dates {
+        match summary.rollup(date).await? {
+            Some(day) => println!("{date}: {:.2} kWh", day.total_kwh),
+            None => println!("{date}: no readings"),
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn data_quality(
+    quality: &DataQuality,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    backfill: bool,
+) -> anyhow::Result<()> {
+    let local_midnight = |date: NaiveDate| {
+        chrono_tz::Australia::Perth
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|time| time.naive_utc())
+            .context("invalid local date")
+    };
+
+    let to = match to {
+        Some(to) => local_midnight(to + chrono::Duration::days(1))?,
+        None => Utc::now().naive_utc(),
+    };
+    let from = match from {
+        Some(from) => local_midnight(from)?,
+        None => to - chrono::Duration::days(1),
+    };
+
+    let report = quality.scan(from, to, backfill).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    Ok(())
+}
+
+pub async fn influx_replay(influx: Option<&InfluxSink>) -> anyhow::Result<()> {
+    let influx = influx.context("influx.url is not configured")?;
+    let written = influx.replay().await?;
+    println!("wrote {written} samples");
+
+    Ok(())
+}
+
+pub async fn webhook_deliveries(
+    webhooks: &Webhooks,
+    name: Option<&str>,
+    limit: i64,
+) -> anyhow::Result<()> {
+    for delivery in webhooks.deliveries(name, limit).await? {
+        let status = delivery
+            .status_code
+            .map(|code| code.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{} {} #{} {} {status} {}ms {}",
+            delivery.attempted_at,
+            delivery.webhook,
+            delivery.outbox_id,
+            delivery.event,
+            delivery.duration_ms,
+            delivery.error.as_deref().unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
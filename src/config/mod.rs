@@ -0,0 +1,400 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use http::HeaderValue;
+use tracing_subscriber::EnvFilter;
+use types::Config;
+
+pub mod types;
+
+/// Used when `SOLAR_CONFIG` is unset. A missing file at this path is not an
+/// error, so deployments configured purely through the environment keep working.
+const DEFAULT_PATH: &str = "config/solar.toml";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("could not read config file {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("could not parse config file {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid value for {key}: {message}")]
+    Invalid { key: &'static str, message: String },
+    #[error("missing required setting {key} (or the {env} environment variable)")]
+    Missing {
+        key: &'static str,
+        env: &'static str,
+    },
+}
+
+impl Config {
+    /// Loads the config file named by `SOLAR_CONFIG`, applies environment
+    /// overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let (path, required) = match std::env::var("SOLAR_CONFIG") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_PATH), false),
+        };
+
+        let mut config = Self::from_file(&path, required)?;
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path, required: bool) -> Result<Self, ConfigError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default());
+            }
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.to_owned(),
+                    source,
+                });
+            }
+        };
+
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// Environment variables take precedence over the file. The names match the
+    /// ones the service has always read.
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        fn parse<T: FromStr>(key: &'static str, value: &str) -> Result<T, ConfigError>
+        where
+            T::Err: Display,
+        {
+            value.parse().map_err(|e: T::Err| ConfigError::Invalid {
+                key,
+                message: e.to_string(),
+            })
+        }
+
+        fn var<T: FromStr>(key: &'static str, target: &mut T) -> Result<(), ConfigError>
+        where
+            T::Err: Display,
+        {
+            if let Ok(value) = std::env::var(key) {
+                *target = parse(key, &value)?;
+            }
+
+            Ok(())
+        }
+
+        fn optional<T: FromStr>(
+            key: &'static str,
+            target: &mut Option<T>,
+        ) -> Result<(), ConfigError>
+        where
+            T::Err: Display,
+        {
+            if let Ok(value) = std::env::var(key) {
+                *target = Some(parse(key, &value)?);
+            }
+
+            Ok(())
+        }
+
+        var("DATABASE_URL", &mut self.database.url)?;
+        var(
+            "DATABASE_MAX_CONNECTIONS",
+            &mut self.database.max_connections,
+        )?;
+        var("LISTEN_ADDR", &mut self.http.listen)?;
+        var("DISCORD_ENABLED", &mut self.discord.enabled)?;
+        optional("DISCORD_TOKEN", &mut self.discord.token)?;
+        var("GOODWE_API_USERNAME", &mut self.goodwe.username)?;
+        var("GOODWE_API_PASSWORD", &mut self.goodwe.password)?;
+        var(
+            "GOODWE_API_POWERSTATION_ID",
+            &mut self.goodwe.powerstation_id,
+        )?;
+        optional("HOME_GATEWAY_BASE_URL", &mut self.home_gateway.base_url)?;
+        var("HOME_GATEWAY_API_KEY", &mut self.home_gateway.api_key)?;
+        optional("INFLUX_URL", &mut self.influx.url)?;
+        var("INFLUX_ORG", &mut self.influx.org)?;
+        var("INFLUX_BUCKET", &mut self.influx.bucket)?;
+        var("INFLUX_TOKEN", &mut self.influx.token)?;
+        optional("INSTANCE_ID", &mut self.leader.instance_id)?;
+        optional("MQTT_HOST", &mut self.mqtt.host)?;
+        var("MQTT_PORT", &mut self.mqtt.port)?;
+        optional("MQTT_USERNAME", &mut self.mqtt.username)?;
+        optional("MQTT_PASSWORD", &mut self.mqtt.password)?;
+        var("POLL_SCHEDULE", &mut self.poller.schedule)?;
+        var(
+            "POLL_NIGHT_INTERVAL_MINS",
+            &mut self.poller.night_interval_mins,
+        )?;
+        optional("INVERTER_CAPACITY_W", &mut self.quality.inverter_capacity_w)?;
+        var("RETENTION_DAYS", &mut self.retention.days)?;
+        optional("PVOUTPUT_API_KEY", &mut self.pvoutput.api_key)?;
+        optional("PVOUTPUT_SYSTEM_ID", &mut self.pvoutput.system_id)?;
+        var("PVOUTPUT_BASE_URL", &mut self.pvoutput.base_url)?;
+        optional("SUMMARY_CHANNEL_ID", &mut self.summary.channel_id)?;
+        optional("ALERT_CHANNEL_ID", &mut self.alerts.channel_id)?;
+        var("ALERT_NO_DATA_MINS", &mut self.alerts.no_data_mins)?;
+        var(
+            "ALERT_DAILY_TOTAL_PERCENT",
+            &mut self.alerts.daily_total_percent,
+        )?;
+        var(
+            "ALERT_FAILURE_THRESHOLD",
+            &mut self.alerts.failure_threshold,
+        )?;
+        var("LOG_FORMAT", &mut self.telemetry.log_format)?;
+        var("RUST_LOG", &mut self.telemetry.log_filter)?;
+        var(
+            "OTEL_EXPORTER_OTLP_PROTOCOL",
+            &mut self.telemetry.otlp_protocol,
+        )?;
+        // OTEL_TRACING_URL is the name this setting had before the standard ones
+        optional("OTEL_TRACING_URL", &mut self.telemetry.otlp_endpoint)?;
+        // the generic variable is a base URL that the signal's path is added
+        // to, while the signal specific ones are used as they are
+        if let Ok(base) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            let protocol = self.telemetry.otlp_protocol;
+            self.telemetry.otlp_endpoint = Some(protocol.signal_endpoint(&base, "traces"));
+            self.telemetry.otlp_metrics_endpoint = Some(protocol.signal_endpoint(&base, "metrics"));
+        }
+        optional(
+            "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+            &mut self.telemetry.otlp_endpoint,
+        )?;
+        optional(
+            "OTEL_EXPORTER_OTLP_METRICS_ENDPOINT",
+            &mut self.telemetry.otlp_metrics_endpoint,
+        )?;
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let required = [
+            ("database.url", "DATABASE_URL", &self.database.url),
+            (
+                "goodwe.username",
+                "GOODWE_API_USERNAME",
+                &self.goodwe.username,
+            ),
+            (
+                "goodwe.password",
+                "GOODWE_API_PASSWORD",
+                &self.goodwe.password,
+            ),
+            (
+                "goodwe.powerstation_id",
+                "GOODWE_API_POWERSTATION_ID",
+                &self.goodwe.powerstation_id,
+            ),
+        ];
+
+        for (key, env, value) in required {
+            if value.is_empty() {
+                return Err(ConfigError::Missing { key, env });
+            }
+        }
+
+        if let Err(e) = EnvFilter::try_new(&self.telemetry.log_filter) {
+            return Err(ConfigError::Invalid {
+                key: "telemetry.log_filter",
+                message: e.to_string(),
+            });
+        }
+
+        for (key, value) in [
+            ("mqtt.topic_prefix", &self.mqtt.topic_prefix),
+            ("mqtt.discovery_prefix", &self.mqtt.discovery_prefix),
+        ] {
+            if value.is_empty() || value.contains(['+', '#']) {
+                return Err(ConfigError::Invalid {
+                    key,
+                    message: "must be a non-empty topic without wildcards".to_string(),
+                });
+            }
+        }
+
+        if self.retention.days < 1 {
+            return Err(ConfigError::Invalid {
+                key: "retention.days",
+                message: "must be at least 1".to_string(),
+            });
+        }
+
+        if self.outbox.max_attempts < 1 {
+            return Err(ConfigError::Invalid {
+                key: "outbox.max_attempts",
+                message: "must be at least 1".to_string(),
+            });
+        }
+
+        for (i, webhook) in self.webhooks.iter().enumerate() {
+            let message = if webhook.name.is_empty() {
+                Some("name must be set")
+            } else if self.webhooks[..i].iter().any(|w| w.name == webhook.name) {
+                Some("name is used by another webhook")
+            } else if webhook.url.is_empty() {
+                Some("url must be set")
+            } else if webhook.secret.is_empty() {
+                Some("secret must be set")
+            } else if webhook.events.is_empty() {
+                Some("events must not be empty")
+            } else {
+                None
+            };
+
+            if let Some(message) = message {
+                return Err(ConfigError::Invalid {
+                    key: "webhooks",
+                    message: format!("{:?}: {message}", webhook.name),
+                });
+            }
+        }
+
+        if let Some(url) = &self.influx.url {
+            if let Err(e) = reqwest::Url::parse(url) {
+                return Err(ConfigError::Invalid {
+                    key: "influx.url",
+                    message: e.to_string(),
+                });
+            }
+
+            for (key, value) in [
+                ("influx.org", &self.influx.org),
+                ("influx.bucket", &self.influx.bucket),
+                ("influx.measurement", &self.influx.measurement),
+            ] {
+                if value.is_empty() {
+                    return Err(ConfigError::Invalid {
+                        key,
+                        message: "must be set when influx.url is".to_string(),
+                    });
+                }
+            }
+        }
+
+        if self.influx.batch_size < 1 {
+            return Err(ConfigError::Invalid {
+                key: "influx.batch_size",
+                message: "must be at least 1".to_string(),
+            });
+        }
+
+        if ![5, 10].contains(&self.pvoutput.status_interval_mins) {
+            return Err(ConfigError::Invalid {
+                key: "pvoutput.status_interval_mins",
+                message: "must be 5 or 10".to_string(),
+            });
+        }
+
+        if !(1..=100).contains(&self.pvoutput.batch_size) {
+            return Err(ConfigError::Invalid {
+                key: "pvoutput.batch_size",
+                message: "must be between 1 and 100".to_string(),
+            });
+        }
+
+        if !(1..=90).contains(&self.pvoutput.max_backfill_days) {
+            return Err(ConfigError::Invalid {
+                key: "pvoutput.max_backfill_days",
+                message: "must be between 1 and 90".to_string(),
+            });
+        }
+
+        if !(0..=180).contains(&self.poller.night_interval_mins) {
+            return Err(ConfigError::Invalid {
+                key: "poller.night_interval_mins",
+                message: "must be between 0 and 180".to_string(),
+            });
+        }
+
+        let timeouts = [
+            self.poller.sems_timeout_secs,
+            self.poller.uv_timeout_secs,
+            self.poller.bom_timeout_secs,
+        ];
+        if timeouts.iter().any(|secs| !(1..=50).contains(secs)) {
+            return Err(ConfigError::Invalid {
+                key: "poller",
+                message: "fetch timeouts must be between 1 and 50 seconds".to_string(),
+            });
+        }
+
+        if self.poller.twilight_mins < 0 {
+            return Err(ConfigError::Invalid {
+                key: "poller.twilight_mins",
+                message: "must not be negative".to_string(),
+            });
+        }
+
+        if self.quality.max_gap_secs < 1 || self.quality.stale_mins < 1 {
+            return Err(ConfigError::Invalid {
+                key: "quality",
+                message: "max_gap_secs and stale_mins must be at least 1".to_string(),
+            });
+        }
+
+        if self.health.degraded_after_mins < 1
+            || self.health.unhealthy_after_mins <= self.health.degraded_after_mins
+        {
+            return Err(ConfigError::Invalid {
+                key: "health",
+                message: "unhealthy_after_mins must be greater than degraded_after_mins, which must be at least 1"
+                    .to_string(),
+            });
+        }
+
+        if self.leader.renew_secs == 0 || self.leader.lease_secs < self.leader.renew_secs * 2 {
+            return Err(ConfigError::Invalid {
+                key: "leader",
+                message: "lease_secs must be at least twice renew_secs, which must be at least 1"
+                    .to_string(),
+            });
+        }
+
+        if self.database.max_connections == 0 {
+            return Err(ConfigError::Invalid {
+                key: "database.max_connections",
+                message: "must be at least 1".to_string(),
+            });
+        }
+
+        if self.http.concurrency_limit == 0 {
+            return Err(ConfigError::Invalid {
+                key: "http.concurrency_limit",
+                message: "must be at least 1".to_string(),
+            });
+        }
+
+        for origin in &self.http.cors_origins {
+            origin
+                .parse::<HeaderValue>()
+                .map_err(|e| ConfigError::Invalid {
+                    key: "http.cors_origins",
+                    message: format!("{origin}: {e}"),
+                })?;
+        }
+
+        if !(0.0..=100.0).contains(&self.alerts.daily_total_percent) {
+            return Err(ConfigError::Invalid {
+                key: "alerts.daily_total_percent",
+                message: "must be between 0 and 100".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
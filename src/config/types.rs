@@ -0,0 +1,479 @@
+use std::{net::SocketAddr, str::FromStr};
+
+use serde::Deserialize;
+use twilight_model::id::{Id, marker::ChannelMarker};
+
+use crate::{alerts::types::AlertRules, webhooks::types::WebhookEvent};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub http: HttpConfig,
+    pub discord: DiscordConfig,
+    pub goodwe: GoodWeConfig,
+    pub health: HealthConfig,
+    pub home_gateway: HomeGatewayConfig,
+    pub influx: InfluxConfig,
+    pub leader: LeaderConfig,
+    pub mqtt: MqttConfig,
+    pub outbox: OutboxConfig,
+    pub poller: PollerConfig,
+    pub pvoutput: PvOutputConfig,
+    pub quality: QualityConfig,
+    pub retention: RetentionConfig,
+    pub summary: SummaryConfig,
+    pub alerts: AlertRules,
+    pub telemetry: TelemetryConfig,
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_connections: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LeaderConfig {
+    /// Names this replica in the lease. Defaults to the hostname and pid.
+    pub instance_id: Option<String>,
+    /// How long a lease lasts without renewal before another replica may
+    /// take over.
+    pub lease_secs: u64,
+    pub renew_secs: u64,
+}
+
+impl Default for LeaderConfig {
+    fn default() -> Self {
+        Self {
+            instance_id: None,
+            lease_secs: 30,
+            renew_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Health is degraded once no reading has been stored for this long.
+    pub degraded_after_mins: i64,
+    /// Health is unhealthy once no reading has been stored for this long.
+    pub unhealthy_after_mins: i64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            degraded_after_mins: 5,
+            unhealthy_after_mins: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub listen: SocketAddr,
+    pub cors_origins: Vec<String>,
+    /// Maximum number of requests handled at once.
+    pub concurrency_limit: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 8000)),
+            cors_origins: vec![
+                "http://localhost:5173".to_string(),
+                "https://solar-panels.anurag.sh".to_string(),
+            ],
+            concurrency_limit: 2048,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub enabled: bool,
+    pub token: Option<String>,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            token: None,
+        }
+    }
+}
+
+impl DiscordConfig {
+    /// The bot token, or `None` when the integration is disabled or unconfigured.
+    pub fn token(&self) -> Option<&str> {
+        self.token
+            .as_deref()
+            .filter(|token| self.enabled && !token.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GoodWeConfig {
+    pub username: String,
+    pub password: String,
+    pub powerstation_id: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HomeGatewayConfig {
+    /// Readings are only pushed when this is set.
+    pub base_url: Option<String>,
+    pub api_key: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InfluxConfig {
+    /// Samples are only written when this is set, e.g. `http://localhost:8086`.
+    pub url: Option<String>,
+    pub org: String,
+    pub bucket: String,
+    pub token: String,
+    pub measurement: String,
+    /// Lines per write request.
+    pub batch_size: i64,
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            org: String::new(),
+            bucket: String::new(),
+            token: String::new(),
+            measurement: "solar".to_string(),
+            batch_size: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboxConfig {
+    /// Deliveries are abandoned after this many failed attempts.
+    pub max_attempts: i32,
+    /// The first retry waits this long, doubling on each further failure.
+    pub base_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 12,
+            base_backoff_secs: 30,
+            max_backoff_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Identifies the target in the outbox and its delivery log.
+    pub name: String,
+    pub url: String,
+    /// Key for the HMAC-SHA256 signature sent in `X-Solar-Signature`.
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+    /// Replaces the JSON body. `{{path}}` placeholders are filled from the
+    /// event envelope, e.g. `{{event}}` or `{{data.power_w}}`. Strings are
+    /// JSON-escaped when `content_type` is JSON.
+    pub template: Option<String>,
+    pub content_type: String,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            url: String::new(),
+            secret: String::new(),
+            events: WebhookEvent::ALL.to_vec(),
+            template: None,
+            content_type: "application/json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Readings are only published when this is set.
+    pub host: Option<String>,
+    pub port: u16,
+    /// Prefix of the client id, which is suffixed with the hostname and pid.
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sensor states are published under `<topic_prefix>/<sensor>`.
+    pub topic_prefix: String,
+    pub discovery_prefix: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: 1883,
+            client_id: "solar-panels".to_string(),
+            username: None,
+            password: None,
+            topic_prefix: "solar".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PvOutputConfig {
+    /// Uploads are only made when this and `system_id` are set.
+    pub api_key: Option<String>,
+    pub system_id: Option<String>,
+    pub base_url: String,
+    /// Status interval configured for the system on PVOutput, 5 or 10.
+    pub status_interval_mins: i32,
+    /// Statuses per `addbatchstatus` request. Donors may send 100.
+    pub batch_size: usize,
+    /// How far back missed data is uploaded. Donors may go back 90 days.
+    pub max_backfill_days: i64,
+}
+
+impl Default for PvOutputConfig {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            system_id: None,
+            base_url: "https://pvoutput.org".to_string(),
+            status_interval_mins: 5,
+            batch_size: 30,
+            max_backfill_days: 14,
+        }
+    }
+}
+
+impl PvOutputConfig {
+    /// The API key and system id, or `None` when uploads are disabled.
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        self.api_key
+            .as_deref()
+            .zip(self.system_id.as_deref())
+            .filter(|(api_key, system_id)| !api_key.is_empty() && !system_id.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollerConfig {
+    /// Schedule for the ingest job, in `tokio-cron-scheduler` English syntax or cron.
+    pub schedule: String,
+    pub summary_schedule: String,
+    /// Minutes between polls overnight, skipping the ticks in between. `0`
+    /// polls on every tick.
+    pub night_interval_mins: i64,
+    /// Polls follow `schedule` from this many minutes before sunrise until
+    /// this many minutes after sunset.
+    pub twilight_mins: i64,
+    /// Time limits for each upstream fetch in a poll, in seconds.
+    pub sems_timeout_secs: u64,
+    pub uv_timeout_secs: u64,
+    pub bom_timeout_secs: u64,
+}
+
+impl Default for PollerConfig {
+    fn default() -> Self {
+        Self {
+            schedule: "every 1 minute".to_string(),
+            summary_schedule: "every 10 minutes".to_string(),
+            night_interval_mins: 30,
+            twilight_mins: 30,
+            sems_timeout_secs: 20,
+            uv_timeout_secs: 10,
+            bom_timeout_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualityConfig {
+    /// Readings above this many watts are reported as out of range.
+    pub inverter_capacity_w: Option<f64>,
+    /// Longer gaps between readings are reported. The default allows for the
+    /// 5 minute resolution of backfilled days.
+    pub max_gap_secs: i64,
+    /// Runs of identical non-zero readings at least this long are reported.
+    pub stale_mins: i64,
+    /// Lets `/api/data-quality?backfill=true` fetch missing days from SEMS.
+    pub api_backfill: bool,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            inverter_capacity_w: None,
+            max_gap_secs: 360,
+            stale_mins: 20,
+            api_backfill: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Delivered outbox entries, webhook deliveries and job runs are deleted
+    /// once they are this many days old.
+    pub days: i32,
+    /// Schedule for the sweep, in `tokio-cron-scheduler` English syntax or cron.
+    pub schedule: String,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            days: 30,
+            schedule: "every 1 hour".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SummaryConfig {
+    pub channel_id: Option<Id<ChannelMarker>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub log_format: LogFormat,
+    /// A `RUST_LOG` style filter directive.
+    pub log_filter: String,
+    /// Spans are only exported when this is set. For HTTP this is the full
+    /// traces URL, for gRPC the collector address. `OTEL_EXPORTER_OTLP_ENDPOINT`
+    /// sets this and `otlp_metrics_endpoint` from a base URL instead.
+    pub otlp_endpoint: Option<String>,
+    /// Defaults to `otlp_endpoint`, with a trailing `/v1/traces` swapped for
+    /// `/v1/metrics` when using HTTP.
+    pub otlp_metrics_endpoint: Option<String>,
+    pub otlp_protocol: OtlpProtocol,
+}
+
+impl TelemetryConfig {
+    pub fn metrics_endpoint(&self) -> Option<String> {
+        if let Some(endpoint) = &self.otlp_metrics_endpoint {
+            return Some(endpoint.clone());
+        }
+
+        let endpoint = self.otlp_endpoint.as_deref()?;
+        match self.otlp_protocol {
+            OtlpProtocol::Grpc => Some(endpoint.to_string()),
+            OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => Some(
+                endpoint
+                    .strip_suffix("/v1/traces")
+                    .map(|base| format!("{base}/v1/metrics"))
+                    .unwrap_or_else(|| endpoint.to_string()),
+            ),
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            log_format: LogFormat::Console,
+            log_filter: "info,otel::tracing=trace".to_string(),
+            otlp_endpoint: None,
+            otlp_metrics_endpoint: None,
+            otlp_protocol: OtlpProtocol::HttpJson,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Console,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "console" => Ok(LogFormat::Console),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "unknown log format {s:?}, expected console or json"
+            )),
+        }
+    }
+}
+
+/// Uses the values of the standard `OTEL_EXPORTER_OTLP_PROTOCOL` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum OtlpProtocol {
+    #[serde(rename = "grpc")]
+    Grpc,
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+    #[serde(rename = "http/json")]
+    HttpJson,
+}
+
+impl OtlpProtocol {
+    /// Endpoint for `signal` (`traces` or `metrics`) given the base URL of
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT`. Over HTTP the spec appends
+    /// `/v1/<signal>`, while gRPC uses the base as is.
+    pub fn signal_endpoint(&self, base: &str, signal: &str) -> String {
+        match self {
+            OtlpProtocol::Grpc => base.to_string(),
+            OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => {
+                format!("{}/v1/{signal}", base.trim_end_matches('/'))
+            }
+        }
+    }
+}
+
+impl FromStr for OtlpProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grpc" => Ok(OtlpProtocol::Grpc),
+            "http/protobuf" => Ok(OtlpProtocol::HttpProtobuf),
+            "http/json" => Ok(OtlpProtocol::HttpJson),
+            _ => Err(format!(
+                "unknown OTLP protocol {s:?}, expected grpc, http/protobuf or http/json"
+            )),
+        }
+    }
+}
@@ -0,0 +1,702 @@
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveDateTime};
+use sqlx::{PgPool, prelude::FromRow};
+use tracing::Instrument;
+use twilight_http::client::InteractionClient;
+use twilight_model::{
+    application::{
+        command::{CommandOptionChoice, CommandOptionChoiceValue},
+        interaction::{
+            Interaction,
+            application_command::{CommandData, CommandOptionValue},
+            message_component::MessageComponentInteractionData,
+        },
+    },
+    channel::message::{
+        Component, Embed, MessageFlags,
+        component::{ActionRow, Button, ButtonStyle},
+    },
+    http::{
+        attachment::Attachment,
+        interaction::{InteractionResponse, InteractionResponseType},
+    },
+};
+use twilight_util::builder::{
+    InteractionResponseDataBuilder,
+    embed::{EmbedBuilder, EmbedFieldBuilder, ImageSource},
+};
+
+use super::{parse_date, string_option, today};
+use crate::{
+    BotContext,
+    chart::{self, HistorySeries},
+    solar_statistics,
+    subscriptions::types::SubscriptionKind,
+    summary,
+    types::{GenerationHistory, SolarCurrentStatistics},
+};
+
+pub async fn solar(
+    interaction: &Interaction,
+    interaction_client: &InteractionClient<'_>,
+    context: &BotContext,
+    data: &CommandData,
+) -> anyhow::Result<()> {
+    interaction_client
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: None,
+            },
+        )
+        .await?;
+
+    let station =
+        string_option(data, "station").unwrap_or_else(|| context.solar_api.powerstation_id());
+    let date = string_option(data, "date").map(parse_date).transpose()?;
+    let compare = string_option(data, "compare")
+        .map(Compare::parse)
+        .transpose()?;
+
+    let embed = match (date, compare) {
+        (None, None) => current_embed(context, station).await?,
+        (Some(date), None) => day_embed(context, station, date).await?,
+        (date, Some(compare)) => {
+            compare_embed(context, station, date.unwrap_or_else(today), compare).await?
+        }
+    };
+
+    interaction_client
+        .update_response(&interaction.token)
+        .embeds(Some(&[embed]))
+        .components(Some(&solar_buttons(station)))
+        .await?;
+
+    Ok(())
+}
+
+/// Handles the buttons under a `/solar` reply by editing the embed in place.
+pub async fn solar_component(
+    interaction: &Interaction,
+    interaction_client: &InteractionClient<'_>,
+    context: &BotContext,
+    data: &MessageComponentInteractionData,
+) -> anyhow::Result<()> {
+    // custom ids look like `solar:<action>:<station>`
+    let mut parts = data.custom_id.splitn(3, ':');
+    let (Some("solar"), Some(action)) = (parts.next(), parts.next()) else {
+        tracing::warn!("unhandled component: {}", data.custom_id);
+        return Ok(());
+    };
+    let station = parts
+        .next()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| context.solar_api.powerstation_id());
+
+    interaction_client
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredUpdateMessage,
+                data: None,
+            },
+        )
+        .await?;
+
+    let embed = match action {
+        "refresh" => current_embed(context, station).await?,
+        "today" => day_embed(context, station, today()).await?,
+        "yesterday" => day_embed(context, station, today() - chrono::Duration::days(1)).await?,
+        "month" => month_embed(context, station).await?,
+        other => anyhow::bail!("unknown action `{other}`"),
+    };
+
+    interaction_client
+        .update_response(&interaction.token)
+        .embeds(Some(&[embed]))
+        .components(Some(&solar_buttons(station)))
+        .await?;
+
+    Ok(())
+}
+
+fn solar_buttons(station: &str) -> [Component; 1] {
+    let button = |action: &str, label: &str| {
+        Component::Button(Button {
+            id: None,
+            custom_id: Some(format!("solar:{action}:{station}")),
+            disabled: false,
+            emoji: None,
+            label: Some(label.to_string()),
+            style: ButtonStyle::Secondary,
+            url: None,
+            sku_id: None,
+        })
+    };
+
+    [Component::ActionRow(ActionRow {
+        id: None,
+        components: vec![
+            button("refresh", "Refresh"),
+            button("today", "Today"),
+            button("yesterday", "Yesterday"),
+            button("month", "This month"),
+        ],
+    })]
+}
+
+/// Names the station in the title when it isn't the configured one.
+fn title(context: &BotContext, title: &str, station: &str) -> String {
+    if station == context.solar_api.powerstation_id() {
+        title.to_string()
+    } else {
+        format!("{title} ({station})")
+    }
+}
+
+async fn current_embed(context: &BotContext, station: &str) -> anyhow::Result<Embed> {
+    let solar_data = context
+        .solar_api
+        .get_latest_saved_solar_data(Some(station))
+        .await?
+        .with_context(|| format!("no readings have been saved for {station}"))?;
+    let SolarCurrentStatistics { averages } =
+        solar_statistics(Some(station), &context.solar_api).await?;
+
+    let embed = EmbedBuilder::new()
+        .title(title(context, "Solar panels", station))
+        .field(
+            EmbedFieldBuilder::new(
+                "Current",
+                format!("{} Wh", solar_data.raw_data.data.kpi.pac),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "UV Level",
+                solar_data
+                    .uv_level
+                    .map(|uv| uv.to_string())
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Total for today",
+                format!("{} kWh", solar_data.raw_data.data.kpi.power),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "15 min avg",
+                format!(
+                    "{} Wh",
+                    averages
+                        .last_15_mins
+                        .map(|r| r.to_string())
+                        .unwrap_or("unknown".to_string())
+                ),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "1 hour avg",
+                format!(
+                    "{} Wh",
+                    averages
+                        .last_1_hour
+                        .map(|r| r.to_string())
+                        .unwrap_or("unknown".to_string())
+                ),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "3 hour avg",
+                format!(
+                    "{} Wh",
+                    averages
+                        .last_3_hours
+                        .map(|r| r.to_string())
+                        .unwrap_or("unknown".to_string())
+                ),
+            )
+            .inline(),
+        )
+        .color(0x40944c)
+        .validate()?
+        .build();
+
+    Ok(embed)
+}
+
+async fn day_embed(context: &BotContext, station: &str, date: NaiveDate) -> anyhow::Result<Embed> {
+    let Some(totals) = context.summary.compute(date, Some(station)).await? else {
+        anyhow::bail!("no data for {date}");
+    };
+
+    let embed = EmbedBuilder::new()
+        .title(title(context, &format!("Solar panels on {date}"), station))
+        .field(EmbedFieldBuilder::new("Total", format!("{} kWh", totals.total_kwh)).inline())
+        .field(EmbedFieldBuilder::new("Peak", format!("{} Wh", totals.peak_w)).inline())
+        .field(
+            EmbedFieldBuilder::new(
+                "Avg UV Level",
+                totals
+                    .avg_uv_level
+                    .map(|uv| format!("{uv:.1}"))
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Avg temperature",
+                totals
+                    .avg_temperature
+                    .map(|t| format!("{t:.1}°C"))
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .color(0x40944c)
+        .validate()?
+        .build();
+
+    Ok(embed)
+}
+
+async fn month_embed(context: &BotContext, station: &str) -> anyhow::Result<Embed> {
+    #[derive(FromRow)]
+    struct Row {
+        best_kwh: Option<f64>,
+        avg_kwh: Option<f64>,
+    }
+
+    let solar_data = context
+        .solar_api
+        .get_latest_saved_solar_data(Some(station))
+        .await?
+        .with_context(|| format!("no readings have been saved for {station}"))?;
+
+    let row: Row = sqlx::query_as(
+        r#"SELECT max(day_kwh) AS best_kwh, avg(day_kwh) AS avg_kwh
+           FROM (
+               SELECT DISTINCT ON ((time + '8 hour')::date) (raw_data->'data'->'kpi'->>'power')::float8 AS day_kwh
+               FROM solar_data_tsdb
+               WHERE date_trunc('month', time + '8 hour') = date_trunc('month', NOW() + '8 hour')
+                 AND NOT backfilled
+                 AND coalesce(station_id, $2) = $1
+               ORDER BY (time + '8 hour')::date, time DESC
+           ) days"#,
+    )
+    .bind(station)
+    .bind(context.solar_api.powerstation_id())
+    .fetch_one(context.solar_api.db())
+    .instrument(tracing::info_span!("month_totals"))
+    .await?;
+
+    let embed = EmbedBuilder::new()
+        .title(title(
+            context,
+            &format!("Solar panels in {}", today().format("%B %Y")),
+            station,
+        ))
+        .field(
+            EmbedFieldBuilder::new(
+                "Total",
+                format!("{} kWh", solar_data.raw_data.data.kpi.month_generation),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Best day",
+                row.best_kwh
+                    .map(|kwh| format!("{kwh} kWh"))
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Daily avg",
+                row.avg_kwh
+                    .map(|kwh| format!("{kwh:.2} kWh"))
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .color(0x40944c)
+        .validate()?
+        .build();
+
+    Ok(embed)
+}
+
+async fn compare_embed(
+    context: &BotContext,
+    station: &str,
+    date: NaiveDate,
+    compare: Compare,
+) -> anyhow::Result<Embed> {
+    let other_date = compare.date_for(date);
+    let (totals, other) = futures::try_join!(
+        context.summary.compute(date, Some(station)),
+        context.summary.compute(other_date, Some(station))
+    )?;
+
+    let Some(totals) = totals else {
+        anyhow::bail!("no data for {date}");
+    };
+
+    let difference = other
+        .as_ref()
+        .map(|other| {
+            let kwh = totals.total_kwh - other.total_kwh;
+            if other.total_kwh > 0.0 {
+                format!("{kwh:+.2} kWh ({:+.0}%)", kwh / other.total_kwh * 100.0)
+            } else {
+                format!("{kwh:+.2} kWh")
+            }
+        })
+        .unwrap_or("unknown".to_string());
+
+    let embed = EmbedBuilder::new()
+        .title(title(
+            context,
+            &format!("Solar panels on {date} vs {}", compare.label()),
+            station,
+        ))
+        .field(
+            EmbedFieldBuilder::new(
+                date.to_string(),
+                format!("{} kWh, peak {} Wh", totals.total_kwh, totals.peak_w),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                other_date.to_string(),
+                other
+                    .as_ref()
+                    .map(|o| format!("{} kWh, peak {} Wh", o.total_kwh, o.peak_w))
+                    .unwrap_or("no data".to_string()),
+            )
+            .inline(),
+        )
+        .field(EmbedFieldBuilder::new("Difference", difference).inline())
+        .color(0x40944c)
+        .validate()?
+        .build();
+
+    Ok(embed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compare {
+    Yesterday,
+    LastWeek,
+    LastYear,
+}
+
+impl Compare {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "yesterday" => Ok(Compare::Yesterday),
+            "last_week" => Ok(Compare::LastWeek),
+            "last_year" => Ok(Compare::LastYear),
+            other => anyhow::bail!("unknown comparison `{other}`"),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Compare::Yesterday => "the day before",
+            Compare::LastWeek => "a week earlier",
+            Compare::LastYear => "a year earlier",
+        }
+    }
+
+    fn date_for(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Compare::Yesterday => date - chrono::Duration::days(1),
+            Compare::LastWeek => date - chrono::Duration::days(7),
+            Compare::LastYear => date
+                .checked_sub_months(chrono::Months::new(12))
+                .unwrap_or(date - chrono::Duration::days(365)),
+        }
+    }
+}
+
+pub async fn autocomplete(
+    interaction: &Interaction,
+    interaction_client: &InteractionClient<'_>,
+    context: &BotContext,
+    data: &CommandData,
+) -> anyhow::Result<()> {
+    let Some((name, value)) = data.options.iter().find_map(|o| match &o.value {
+        CommandOptionValue::Focused(value, _) => Some((o.name.as_str(), value.as_str())),
+        _ => None,
+    }) else {
+        return Ok(());
+    };
+
+    let value = value.trim().to_lowercase();
+    let suggestions = match name {
+        "date" => {
+            let today = today();
+            let mut dates = vec!["today".to_string(), "yesterday".to_string()];
+            dates.extend((0..30).map(|days| (today - chrono::Duration::days(days)).to_string()));
+            dates
+        }
+        "station" => station_ids(context).await?,
+        _ => vec![],
+    };
+
+    let choices = suggestions
+        .into_iter()
+        .filter(|s| s.to_lowercase().starts_with(&value))
+        .take(25)
+        .map(|s| CommandOptionChoice {
+            name: s.clone(),
+            name_localizations: None,
+            value: CommandOptionChoiceValue::String(s),
+        });
+
+    interaction_client
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+                data: Some(
+                    InteractionResponseDataBuilder::new()
+                        .choices(choices)
+                        .build(),
+                ),
+            },
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Stations that have reported recently, which always includes the configured
+/// one.
+async fn station_ids(context: &BotContext) -> Result<Vec<String>, anyhow::Error> {
+    let mut stations: Vec<String> = sqlx::query_scalar(
+        r#"SELECT DISTINCT coalesce(station_id, $1)
+           FROM solar_data_tsdb
+           WHERE time > NOW() - INTERVAL '7 days'"#,
+    )
+    .bind(context.solar_api.powerstation_id())
+    .fetch_all(context.solar_api.db())
+    .instrument(tracing::info_span!("station_ids"))
+    .await?;
+
+    let configured = context.solar_api.powerstation_id();
+    if !stations.iter().any(|s| s == configured) {
+        stations.push(configured.to_string());
+    }
+    stations.sort();
+
+    Ok(stations)
+}
+
+pub async fn history(
+    interaction: &Interaction,
+    interaction_client: &InteractionClient<'_>,
+    context: &BotContext,
+    data: &CommandData,
+) -> anyhow::Result<()> {
+    interaction_client
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: None,
+            },
+        )
+        .await?;
+
+    let date = parse_date(string_option(data, "date").unwrap_or("today"))?;
+    let compare = string_option(data, "compare").map(parse_date).transpose()?;
+
+    let history = history_for_date(date, context.solar_api.db()).await?;
+    if history.is_empty() {
+        anyhow::bail!("no data for {date}");
+    }
+
+    let compare = match compare {
+        Some(compare) => Some((
+            compare,
+            history_for_date(compare, context.solar_api.db()).await?,
+        )),
+        None => None,
+    };
+
+    let title = match &compare {
+        Some((compare, _)) => format!("Solar generation for {date} vs {compare}"),
+        None => format!("Solar generation for {date}"),
+    };
+
+    let png = {
+        let title = title.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut series = vec![HistorySeries {
+                label: date.to_string(),
+                history: &history,
+                colour: chart::TODAY_COLOUR,
+                overlays: true,
+            }];
+
+            if let Some((compare, compare_history)) = &compare {
+                series.push(HistorySeries {
+                    label: compare.to_string(),
+                    history: compare_history,
+                    colour: chart::COMPARE_COLOUR,
+                    overlays: false,
+                });
+            }
+
+            chart::render_history(&title, &series)
+        })
+        .await??
+    };
+
+    let embed = EmbedBuilder::new()
+        .title(title)
+        .image(ImageSource::attachment("history.png")?)
+        .color(0x40944c)
+        .validate()?
+        .build();
+
+    interaction_client
+        .update_response(&interaction.token)
+        .embeds(Some(&[embed]))
+        .attachments(&[Attachment::from_bytes("history.png".to_string(), png, 1)])
+        .await?;
+
+    Ok(())
+}
+
+pub async fn summary(
+    interaction: &Interaction,
+    interaction_client: &InteractionClient<'_>,
+    context: &BotContext,
+    data: &CommandData,
+) -> anyhow::Result<()> {
+    interaction_client
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: None,
+            },
+        )
+        .await?;
+
+    let date = parse_date(string_option(data, "date").unwrap_or("today"))?;
+    let Some(report) = context.summary.report(date).await? else {
+        anyhow::bail!("no data for {date}");
+    };
+
+    interaction_client
+        .update_response(&interaction.token)
+        .embeds(Some(&[summary::embed(&report)?]))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn subscription(
+    interaction: &Interaction,
+    interaction_client: &InteractionClient<'_>,
+    context: &BotContext,
+    data: &CommandData,
+) -> anyhow::Result<()> {
+    interaction_client
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: Some(
+                    InteractionResponseDataBuilder::new()
+                        .flags(MessageFlags::EPHEMERAL)
+                        .build(),
+                ),
+            },
+        )
+        .await?;
+
+    let user_id = interaction
+        .author_id()
+        .context("interaction has no author")?;
+    let kind = string_option(data, "kind")
+        .and_then(SubscriptionKind::parse)
+        .context("unknown subscription")?;
+
+    let content = if data.name == "subscribe" {
+        if context.subscriptions.subscribe(user_id, kind).await? {
+            format!("Subscribed to {}, updates will arrive by DM", kind.title())
+        } else {
+            format!("Already subscribed to {}", kind.title())
+        }
+    } else if context.subscriptions.unsubscribe(user_id, kind).await? {
+        format!("Unsubscribed from {}", kind.title())
+    } else {
+        format!("Not subscribed to {}", kind.title())
+    };
+
+    interaction_client
+        .update_response(&interaction.token)
+        .content(Some(&content))
+        .await?;
+
+    Ok(())
+}
+
+/// Five minute averages for a single local day.
+async fn history_for_date(
+    date: NaiveDate,
+    db: &PgPool,
+) -> Result<Vec<GenerationHistory>, anyhow::Error> {
+    #[derive(FromRow)]
+    struct Row {
+        avg_wh: Option<f64>,
+        avg_uv_level: Option<f64>,
+        avg_temp: Option<f64>,
+        bucket_time: NaiveDateTime,
+    }
+
+    let rows: Vec<Row> = sqlx::query_as(
+        "SELECT avg(current_kwh) as avg_wh, avg(uv_level) as avg_uv_level, avg(temperature) as avg_temp, time_bucket('5 minutes', time) as bucket_time FROM solar_data_tsdb WHERE (time + '8 hour')::date = $1 GROUP BY bucket_time ORDER BY bucket_time ASC",
+    )
+    .bind(date)
+    .fetch_all(db)
+    .instrument(tracing::info_span!("history_for_date", %date))
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| GenerationHistory {
+            uv_level: r.avg_uv_level,
+            temperature: r.avg_temp,
+            at: r.bucket_time,
+            wh: r.avg_wh.unwrap_or_default(),
+            timestamp: r.bucket_time.and_utc().timestamp_millis(),
+        })
+        .collect())
+}
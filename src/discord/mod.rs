@@ -0,0 +1,363 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use tokio_util::sync::CancellationToken;
+use twilight_cache_inmemory::{DefaultCacheModels, InMemoryCacheBuilder, ResourceType};
+use twilight_gateway::{
+    CloseFrame, ConfigBuilder, Event, EventType, EventTypeFlags, Intents, Shard, ShardId, StreamExt,
+};
+use twilight_http::{Client as HttpClient, client::InteractionClient};
+use twilight_model::{
+    application::{
+        command::{Command, CommandType},
+        interaction::{
+            Interaction, InteractionContextType, InteractionData, InteractionType,
+            application_command::{CommandData, CommandOptionValue},
+        },
+    },
+    id::{Id, marker::ApplicationMarker},
+    oauth::ApplicationIntegrationType,
+};
+use twilight_util::builder::{
+    command::{CommandBuilder, StringBuilder},
+    embed::EmbedBuilder,
+};
+
+use crate::{BotContext, subscriptions::types::SubscriptionKind};
+
+mod commands;
+
+async fn handle_event(event: Event, _http: Arc<HttpClient>) -> anyhow::Result<()> {
+    match event {
+        Event::GatewayHeartbeatAck
+        | Event::MessageCreate(_)
+        | Event::MessageUpdate(_)
+        | Event::MessageDelete(_) => {}
+        // Other events here...
+        e => {
+            tracing::warn!("unhandled event: {e:?}")
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_interaction_error(
+    interaction: &Interaction,
+    interaction_client: &InteractionClient<'_>,
+    error: anyhow::Error,
+) {
+    let fut = async {
+        let error = if error.to_string().contains("Missing Access") {
+            "This channel is not accessible to the bot...".to_string()
+        } else {
+            error.to_string()
+        };
+
+        let embed = EmbedBuilder::new()
+            .title("oops")
+            .description(error)
+            .color(0xcc6666)
+            .validate()?
+            .build();
+
+        interaction_client
+            .update_response(&interaction.token)
+            .embeds(Some(&[embed]))
+            .await?;
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    if let Err(e) = fut.await {
+        tracing::error!("error in updating message: {e:?}");
+    }
+
+    tracing::error!("error in interaction: {error:?}");
+}
+
+/// Dispatches an incoming interaction to the matching command handler,
+/// routing any error to the shared error handler.
+async fn process_interaction(
+    interaction: Interaction,
+    http: Arc<HttpClient>,
+    app_id: Id<ApplicationMarker>,
+    context: BotContext,
+) {
+    if !matches!(
+        interaction.kind,
+        InteractionType::ApplicationCommand
+            | InteractionType::ApplicationCommandAutocomplete
+            | InteractionType::MessageComponent
+    ) {
+        return;
+    }
+
+    let interaction_client = http.interaction(app_id);
+
+    let data = match &interaction.data {
+        Some(InteractionData::ApplicationCommand(data)) => data,
+        Some(InteractionData::MessageComponent(data)) => {
+            if let Err(error) =
+                commands::solar_component(&interaction, &interaction_client, &context, data).await
+            {
+                handle_interaction_error(&interaction, &interaction_client, error).await;
+            }
+
+            return;
+        }
+        _ => return,
+    };
+
+    if interaction.kind == InteractionType::ApplicationCommandAutocomplete {
+        if let Err(e) =
+            commands::autocomplete(&interaction, &interaction_client, &context, data).await
+        {
+            tracing::error!("error in autocomplete: {e:?}");
+        }
+
+        return;
+    }
+
+    match data.name.as_str() {
+        "solar" => {
+            if let Err(error) =
+                commands::solar(&interaction, &interaction_client, &context, data).await
+            {
+                handle_interaction_error(&interaction, &interaction_client, error).await;
+            }
+        }
+        "history" => {
+            if let Err(error) =
+                commands::history(&interaction, &interaction_client, &context, data).await
+            {
+                handle_interaction_error(&interaction, &interaction_client, error).await;
+            }
+        }
+        "summary" => {
+            if let Err(error) =
+                commands::summary(&interaction, &interaction_client, &context, data).await
+            {
+                handle_interaction_error(&interaction, &interaction_client, error).await;
+            }
+        }
+        "subscribe" | "unsubscribe" => {
+            if let Err(error) =
+                commands::subscription(&interaction, &interaction_client, &context, data).await
+            {
+                handle_interaction_error(&interaction, &interaction_client, error).await;
+            }
+        }
+        other => tracing::warn!("unhandled command: {other}"),
+    }
+}
+
+fn string_option<'a>(data: &'a CommandData, name: &str) -> Option<&'a str> {
+    data.options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match &o.value {
+            CommandOptionValue::String(value) => Some(value.as_str()),
+            _ => None,
+        })
+}
+
+fn today() -> NaiveDate {
+    chrono::offset::Utc::now()
+        .with_timezone(&chrono_tz::Australia::Perth)
+        .date_naive()
+}
+
+/// Parses a date given to a command, accepting `today`, `yesterday` or `YYYY-MM-DD`.
+fn parse_date(value: &str) -> anyhow::Result<NaiveDate> {
+    let today = today();
+
+    match value.trim().to_lowercase().as_str() {
+        "today" => Ok(today),
+        "yesterday" => Ok(today - chrono::Duration::days(1)),
+        other => NaiveDate::parse_from_str(other, "%Y-%m-%d")
+            .with_context(|| format!("invalid date `{value}`, expected YYYY-MM-DD")),
+    }
+}
+
+fn commands() -> [Command; 5] {
+    let solar_command =
+        CommandBuilder::new("solar", "get latest solar details", CommandType::ChatInput)
+            .option(
+                StringBuilder::new("date", "show totals for a day (YYYY-MM-DD)").autocomplete(true),
+            )
+            .option(StringBuilder::new("station", "power station to show").autocomplete(true))
+            .option(
+                StringBuilder::new("compare", "compare against another day").choices([
+                    ("Yesterday", "yesterday"),
+                    ("Last week", "last_week"),
+                    ("Same day last year", "last_year"),
+                ]),
+            )
+            .integration_types(vec![
+                ApplicationIntegrationType::GuildInstall,
+                ApplicationIntegrationType::UserInstall,
+            ])
+            .contexts(vec![
+                InteractionContextType::BotDm,
+                InteractionContextType::PrivateChannel,
+                InteractionContextType::Guild,
+            ])
+            .build();
+
+    let history_command = CommandBuilder::new(
+        "history",
+        "chart solar generation for a day",
+        CommandType::ChatInput,
+    )
+    .option(StringBuilder::new(
+        "date",
+        "day to chart (YYYY-MM-DD, today or yesterday), defaults to today",
+    ))
+    .option(StringBuilder::new(
+        "compare",
+        "day to compare against (YYYY-MM-DD, today or yesterday)",
+    ))
+    .integration_types(vec![
+        ApplicationIntegrationType::GuildInstall,
+        ApplicationIntegrationType::UserInstall,
+    ])
+    .contexts(vec![
+        InteractionContextType::BotDm,
+        InteractionContextType::PrivateChannel,
+        InteractionContextType::Guild,
+    ])
+    .build();
+
+    let summary_command = CommandBuilder::new(
+        "summary",
+        "get the solar summary for a day",
+        CommandType::ChatInput,
+    )
+    .option(StringBuilder::new(
+        "date",
+        "day to summarise (YYYY-MM-DD, today or yesterday), defaults to today",
+    ))
+    .integration_types(vec![
+        ApplicationIntegrationType::GuildInstall,
+        ApplicationIntegrationType::UserInstall,
+    ])
+    .contexts(vec![
+        InteractionContextType::BotDm,
+        InteractionContextType::PrivateChannel,
+        InteractionContextType::Guild,
+    ])
+    .build();
+
+    let subscription_choices = SubscriptionKind::ALL.map(|kind| (kind.title(), kind.as_str()));
+    let [subscribe_command, unsubscribe_command] = [
+        ("subscribe", "get solar updates by DM"),
+        ("unsubscribe", "stop getting solar updates by DM"),
+    ]
+    .map(|(name, description)| {
+        CommandBuilder::new(name, description, CommandType::ChatInput)
+            .option(
+                StringBuilder::new("kind", "which updates")
+                    .required(true)
+                    .choices(subscription_choices),
+            )
+            .integration_types(vec![
+                ApplicationIntegrationType::GuildInstall,
+                ApplicationIntegrationType::UserInstall,
+            ])
+            .contexts(vec![
+                InteractionContextType::BotDm,
+                InteractionContextType::PrivateChannel,
+                InteractionContextType::Guild,
+            ])
+            .build()
+    });
+
+    [
+        solar_command,
+        history_command,
+        summary_command,
+        subscribe_command,
+        unsubscribe_command,
+    ]
+}
+
+/// Registers the slash commands and runs the gateway event loop until the
+/// shard closes. Once `shutdown` is cancelled the shard is sent a normal close
+/// frame and the loop drains until Discord acknowledges it.
+pub async fn run(
+    token: String,
+    http: Arc<HttpClient>,
+    context: BotContext,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let config = ConfigBuilder::new(token, Intents::GUILD_MESSAGES).build();
+    let mut shard = Shard::with_config(ShardId::ONE, config);
+    let cache = InMemoryCacheBuilder::<DefaultCacheModels>::new()
+        .resource_types(ResourceType::MESSAGE | ResourceType::GUILD)
+        .build();
+
+    let app_id = http.current_user_application().await?.model().await?.id;
+    let interaction_client = http.interaction(app_id);
+
+    let commands = commands();
+
+    tracing::info!("updating commands: {}", commands.len());
+    interaction_client.set_global_commands(&commands).await?;
+
+    tracing::info!("starting event loop");
+    let mut closing = false;
+    loop {
+        let event = tokio::select! {
+            _ = shutdown.cancelled(), if !closing => {
+                tracing::info!("closing shard");
+                shard.close(CloseFrame::NORMAL);
+                closing = true;
+                continue;
+            }
+            event = shard.next_event(EventTypeFlags::all()) => event,
+        };
+
+        let Some(event) = event else {
+            break;
+        };
+
+        let Ok(event) = event else {
+            let source = event.unwrap_err();
+            tracing::warn!(source = ?source, "error receiving event");
+
+            continue;
+        };
+
+        if matches!(event.kind(), EventType::GatewayHeartbeatAck) {
+            continue;
+        }
+
+        if closing && matches!(event.kind(), EventType::GatewayClose) {
+            tracing::info!("shard closed");
+            break;
+        }
+
+        cache.update(&event);
+
+        if matches!(event.kind(), EventType::Ready) {
+            tracing::info!("connected on shard");
+            continue;
+        }
+
+        if let Event::InteractionCreate(i) = event {
+            let http = Arc::clone(&http);
+            let context = context.clone();
+            tokio::spawn(async move {
+                process_interaction(i.0, http, app_id, context).await;
+            });
+
+            continue;
+        }
+
+        tokio::spawn(handle_event(event, Arc::clone(&http)));
+    }
+
+    Ok(())
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use axum::http::{HeaderMap, HeaderValue};
 use base64::{Engine, prelude::BASE64_STANDARD};
+use chrono::{NaiveDate, NaiveTime, TimeZone};
 use reqwest::{
     Method,
     header::{ACCEPT, CONTENT_TYPE},
@@ -11,10 +12,14 @@ use reqwest_tracing::{DisableOtelPropagation, TracingMiddleware};
 use sqlx::PgPool;
 use tracing::instrument;
 use types::{
-    LoginData, LoginRequest, LoginResponse, PlantDetailsByPowerStationIdResponse, SavedSolarData,
+    LoginData, LoginRequest, LoginResponse, PlantDetailsByPowerStationIdResponse,
+    PlantPowerChartPoint, PlantPowerChartRequest, PlantPowerChartResponse, SavedSolarData,
 };
 
-use crate::tracing_setup::TimeTrace;
+use crate::{
+    metrics::{Timed, metrics},
+    tracing_setup::TimeTrace,
+};
 
 pub mod types;
 
@@ -30,6 +35,8 @@ pub struct GoodWeSemsAPI {
 const LOGIN_URL: &str = "https://www.semsportal.com/api/v2/Common/CrossLogin";
 const GET_POWERSTATION_DETAILS_URL: &str =
     "https://au.semsportal.com/api/v3/PowerStation/GetPlantDetailByPowerstationId";
+const GET_PLANT_POWER_CHART_URL: &str =
+    "https://au.semsportal.com/api/v2/Charts/GetPlantPowerChart";
 
 #[derive(thiserror::Error, Debug)]
 pub enum GoodWeSemsAPIError {
@@ -39,6 +46,10 @@ pub enum GoodWeSemsAPIError {
     Http(#[from] reqwest::Error),
     #[error("a database error occurred: {0}")]
     Database(#[from] sqlx::Error),
+    #[error("a deserialisation error occurred: {0}")]
+    Deserialize(#[from] serde_json::Error),
+    #[error("invalid chart time {0:?}")]
+    ChartTime(String),
 }
 
 impl GoodWeSemsAPI {
@@ -68,19 +79,36 @@ impl GoodWeSemsAPI {
         }
     }
 
+    pub fn powerstation_id(&self) -> &str {
+        &self.powerstation_id
+    }
+
+    /// Latest reading, across every station when `station` is `None`.
+    /// Readings stored before stations were recorded belong to the
+    /// configured station.
     #[tracing::instrument(skip(self))]
-    pub async fn get_latest_saved_solar_data(&self) -> Result<SavedSolarData, GoodWeSemsAPIError> {
+    pub async fn get_latest_saved_solar_data(
+        &self,
+        station: Option<&str>,
+    ) -> Result<Option<SavedSolarData>, GoodWeSemsAPIError> {
         let solar_data = sqlx::query!(
-            "SELECT raw_data, temperature, uv_level FROM solar_data_tsdb ORDER BY time DESC LIMIT 1"
+            "SELECT raw_data, temperature, uv_level FROM solar_data_tsdb WHERE NOT backfilled AND ($1::text IS NULL OR coalesce(station_id, $2) = $1) ORDER BY time DESC LIMIT 1",
+            station,
+            self.powerstation_id
         )
-        .fetch_one(&self.db)
+        .fetch_optional(&self.db)
+        .timed("latest_reading")
         .await?;
 
-        Ok(SavedSolarData {
-            raw_data: serde_json::from_value(solar_data.raw_data).unwrap(),
-            temperature: solar_data.temperature,
-            uv_level: solar_data.uv_level,
-        })
+        solar_data
+            .map(|solar_data| {
+                Ok(SavedSolarData {
+                    raw_data: serde_json::from_value(solar_data.raw_data)?,
+                    temperature: solar_data.temperature,
+                    uv_level: solar_data.uv_level,
+                })
+            })
+            .transpose()
     }
 
     #[instrument(skip(self))]
@@ -113,6 +141,92 @@ impl GoodWeSemsAPI {
         Ok(response)
     }
 
+    /// Fetches the power curve SEMS recorded for a past local date, used to fill
+    /// gaps when the poller was down.
+    #[instrument(skip(self, login))]
+    pub async fn get_power_chart(
+        &self,
+        login: LoginData,
+        date: NaiveDate,
+    ) -> Result<Vec<PlantPowerChartPoint>, GoodWeSemsAPIError> {
+        let request = self
+            .http
+            .request(Method::POST, GET_PLANT_POWER_CHART_URL)
+            .json(&PlantPowerChartRequest {
+                id: self.powerstation_id.clone(),
+                date: date.format("%Y-%m-%d").to_string(),
+                full_script: false,
+            })
+            .header(
+                "token",
+                BASE64_STANDARD.encode(serde_json::to_string(&login).unwrap()),
+            )
+            .build()?;
+
+        let response = self
+            .http
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .json::<PlantPowerChartResponse>()
+            .await?;
+
+        Ok(response
+            .data
+            .lines
+            .into_iter()
+            .find(|line| line.key == "PCurve_Power")
+            .map(|line| line.xy)
+            .unwrap_or_default())
+    }
+
+    /// Stores the power curve for a past local date wherever no reading exists
+    /// within 150 seconds, returning the number of readings inserted.
+    #[instrument(skip(self, login))]
+    pub async fn backfill_date(
+        &self,
+        login: LoginData,
+        date: NaiveDate,
+    ) -> Result<u64, GoodWeSemsAPIError> {
+        let points = self.get_power_chart(login, date).await?;
+        let mut inserted = 0;
+
+        for point in points {
+            let Some(watts) = point.y else {
+                continue;
+            };
+
+            let time = NaiveTime::parse_from_str(&point.x, "%H:%M")
+                .map_err(|_| GoodWeSemsAPIError::ChartTime(point.x.clone()))?;
+            let Some(local) = chrono_tz::Australia::Perth
+                .from_local_datetime(&date.and_time(time))
+                .single()
+            else {
+                continue;
+            };
+
+            let result = sqlx::query(
+                r#"INSERT INTO solar_data_tsdb (time, current_kwh, raw_data, station_id, backfilled)
+                   SELECT $1, $2, $3, $4, true
+                   WHERE NOT EXISTS (
+                       SELECT 1 FROM solar_data_tsdb
+                       WHERE (station_id = $4 OR station_id IS NULL)
+                         AND time BETWEEN $1 - interval '150 seconds' AND $1 + interval '150 seconds'
+                   )"#,
+            )
+            .bind(local.naive_utc())
+            .bind(watts)
+            .bind(serde_json::json!({ "source": "backfill", "point": point }))
+            .bind(&self.powerstation_id)
+            .execute(&self.db)
+            .await?;
+
+            inserted += result.rows_affected();
+        }
+
+        Ok(inserted)
+    }
+
     #[instrument(skip(self))]
     pub async fn get_new_or_cached_login_data(&self) -> Result<LoginData, GoodWeSemsAPIError> {
         let latest_login_data =
@@ -140,6 +254,7 @@ impl GoodWeSemsAPI {
 
     async fn login_and_save(&self) -> Result<LoginData, GoodWeSemsAPIError> {
         let response = self.login().await?;
+        metrics().record_sems_login();
         let login_data = serde_json::to_value(&response.data).unwrap();
         sqlx::query!(
             "INSERT INTO cached_token (login_data) VALUES ($1)",
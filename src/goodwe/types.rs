@@ -74,3 +74,37 @@ pub struct Kpi {
     pub yield_rate: f64,
     pub currency: String,
 }
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlantPowerChartRequest {
+    pub id: String,
+    pub date: String,
+    pub full_script: bool,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlantPowerChartResponse {
+    pub data: PlantPowerChartData,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlantPowerChartData {
+    pub lines: Vec<PlantPowerChartLine>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlantPowerChartLine {
+    pub key: String,
+    pub xy: Vec<PlantPowerChartPoint>,
+}
+
+/// A single point on the day's power curve: local `HH:MM` and watts.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlantPowerChartPoint {
+    pub x: String,
+    pub y: Option<f64>,
+}
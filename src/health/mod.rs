@@ -0,0 +1,136 @@
+use chrono::{NaiveDateTime, Utc};
+use sqlx::PgPool;
+use tracing::instrument;
+use types::{HealthReport, HealthStatus, JobRun, UpstreamHealth};
+
+use crate::{
+    background::schedule::PollSchedule,
+    background::{BackgroundTask, JobOutcome},
+    config::types::HealthConfig,
+    leader::{LeaderElection, LeaderError},
+    metrics::{Timed, Upstream},
+    outbox::{Outbox, OutboxError},
+};
+
+pub mod types;
+
+/// Derives service health from recorded poll runs.
+#[derive(Clone)]
+pub struct HealthCheck {
+    db: PgPool,
+    config: HealthConfig,
+    outbox: Outbox,
+    leader: LeaderElection,
+    schedule: PollSchedule,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HealthError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("an outbox error occurred: {0}")]
+    Outbox(#[from] OutboxError),
+    #[error("a leader election error occurred: {0}")]
+    Leader(#[from] LeaderError),
+}
+
+impl HealthCheck {
+    pub fn new(
+        db: PgPool,
+        config: HealthConfig,
+        outbox: Outbox,
+        leader: LeaderElection,
+        schedule: PollSchedule,
+    ) -> Self {
+        Self {
+            db,
+            config,
+            outbox,
+            leader,
+            schedule,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn check(&self) -> Result<HealthReport, HealthError> {
+        let last_run: Option<JobRun> = sqlx::query_as(
+            r#"SELECT started_at, finished_at, outcome, steps, error
+               FROM job_runs
+               WHERE job = $1
+               ORDER BY started_at DESC
+               LIMIT 1"#,
+        )
+        .bind(BackgroundTask::JOB)
+        .fetch_optional(&self.db)
+        .timed("health_last_run")
+        .await?;
+
+        // Falls back to the newest polled reading so a restart, or readings
+        // from before runs were recorded, don't count as an outage.
+        let last_success_at: Option<NaiveDateTime> = sqlx::query_scalar(
+            r#"SELECT GREATEST(
+                   (SELECT max(finished_at) FROM job_runs WHERE job = $1 AND outcome <> $2),
+                   (SELECT max(time) FROM solar_data_tsdb
+                    WHERE time > now() - interval '1 day' AND NOT backfilled)
+               )"#,
+        )
+        .bind(BackgroundTask::JOB)
+        .bind(JobOutcome::Failure.as_str())
+        .fetch_one(&self.db)
+        .timed("health_last_success")
+        .await?;
+
+        let outbox = self.outbox.depth().await?;
+        let leader = self.leader.status().await?;
+
+        let mut status = HealthStatus::Ok;
+        let mut reasons = Vec::new();
+
+        let now = Utc::now();
+        let seconds_since_success = last_success_at.map(|at| (now.naive_utc() - at).num_seconds());
+        // polls are spaced out overnight, so allow for the wait between them
+        let allowance = self.schedule.interval(now).num_seconds();
+        match seconds_since_success {
+            None => {
+                status = HealthStatus::Unhealthy;
+                reasons.push("no successful ingest in the last day".to_string());
+            }
+            Some(secs) if secs >= self.config.unhealthy_after_mins * 60 + allowance => {
+                status = HealthStatus::Unhealthy;
+                reasons.push(format!("last successful ingest was {}m ago", secs / 60));
+            }
+            Some(secs) if secs >= self.config.degraded_after_mins * 60 + allowance => {
+                status = HealthStatus::Degraded;
+                reasons.push(format!("last successful ingest was {}m ago", secs / 60));
+            }
+            Some(_) => {}
+        }
+
+        let upstreams: Vec<UpstreamHealth> = last_run
+            .iter()
+            .flat_map(|run| run.steps.iter())
+            .filter(|step| Upstream::ALL.iter().any(|u| u.as_str() == step.name))
+            .map(|step| UpstreamHealth {
+                name: step.name.clone(),
+                ok: step.error.is_none(),
+                error: step.error.clone(),
+            })
+            .collect();
+
+        for upstream in upstreams.iter().filter(|u| !u.ok) {
+            status = status.max(HealthStatus::Degraded);
+            reasons.push(format!("{} failed in the latest run", upstream.name));
+        }
+
+        Ok(HealthReport {
+            status,
+            last_success_at,
+            seconds_since_success,
+            last_run,
+            upstreams,
+            reasons,
+            outbox,
+            leader,
+        })
+    }
+}
@@ -0,0 +1,47 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{prelude::FromRow, types::Json};
+
+use crate::{background::RunStep, leader::types::LeaderStatus, outbox::types::OutboxDepth};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Degraded,
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// When a reading was last stored, in UTC.
+    pub last_success_at: Option<NaiveDateTime>,
+    pub seconds_since_success: Option<i64>,
+    pub last_run: Option<JobRun>,
+    pub upstreams: Vec<UpstreamHealth>,
+    /// Why the status is not `ok`.
+    pub reasons: Vec<String>,
+    pub outbox: OutboxDepth,
+    pub leader: LeaderStatus,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRun {
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub outcome: String,
+    pub steps: Json<Vec<RunStep>>,
+    pub error: Option<String>,
+}
+
+/// Result of an upstream's step in the latest run.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpstreamHealth {
+    pub name: String,
+    pub ok: bool,
+    pub error: Option<String>,
+}
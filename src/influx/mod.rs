@@ -0,0 +1,278 @@
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDateTime, Utc};
+use reqwest::{StatusCode, Url, header::CONTENT_TYPE};
+use reqwest_tracing::TracingMiddleware;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+use types::{ExportCursor, Sample};
+
+use crate::{
+    config::types::InfluxConfig,
+    leader::LeaderElection,
+    metrics::{Timed, Upstream, metrics},
+    tracing_setup::TimeTrace,
+};
+
+pub mod types;
+
+/// Writes stored readings to an InfluxDB v2 bucket in line protocol.
+#[derive(Clone)]
+pub struct InfluxSink {
+    db: PgPool,
+    config: InfluxConfig,
+    write_url: Url,
+    http_client: reqwest_middleware::ClientWithMiddleware,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InfluxError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("a http error occurred: {0}")]
+    Http(#[from] reqwest_middleware::Error),
+    #[error("influxdb responded with {status}: {body}")]
+    Rejected { status: StatusCode, body: String },
+}
+
+impl InfluxSink {
+    const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// Returns `None` when no URL is configured.
+    pub fn new(db: PgPool, config: InfluxConfig) -> Option<Self> {
+        let url = config.url.as_deref()?;
+        let write_url = Url::parse_with_params(
+            &format!("{}/api/v2/write", url.trim_end_matches('/')),
+            [
+                ("org", config.org.as_str()),
+                ("bucket", config.bucket.as_str()),
+                ("precision", "ms"),
+            ],
+        )
+        .expect("influx.url is validated when loading the config");
+
+        Some(Self {
+            db,
+            config,
+            write_url,
+            http_client: reqwest_middleware::ClientBuilder::new(
+                reqwest::ClientBuilder::new()
+                    .timeout(Duration::from_secs(30))
+                    .build()
+                    .unwrap(),
+            )
+            .with(TracingMiddleware::<TimeTrace>::new())
+            .build(),
+        })
+    }
+
+    /// Writes new readings every 30 seconds until `shutdown` is cancelled.
+    pub async fn run(&self, leader: LeaderElection, shutdown: CancellationToken) {
+        loop {
+            if leader.is_leader()
+                && let Err(e) = self.export_new().await
+            {
+                tracing::error!("error writing to influxdb: {e}");
+            }
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(Self::POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Writes the whole of `solar_data_tsdb`, returning the number of samples.
+    /// Points already in the bucket are overwritten with the same values.
+    pub async fn replay(&self) -> Result<usize, InfluxError> {
+        self.export_after(None).await
+    }
+
+    #[instrument(name = "InfluxSink::export_new", skip(self), fields(otel.kind = "internal"))]
+    async fn export_new(&self) -> Result<usize, InfluxError> {
+        let cursor: Option<ExportCursor> = sqlx::query_as(
+            "SELECT exported_until, exported_id, backfill_exported_id FROM influx_state",
+        )
+        .fetch_optional(&self.db)
+        .timed("influx_state")
+        .await?;
+
+        // history is left to `replay`, so start from the first run
+        let cursor = match cursor {
+            Some(cursor) => cursor,
+            None => {
+                let newest_id: Option<i64> =
+                    sqlx::query_scalar("SELECT max(id) FROM solar_data_tsdb")
+                        .fetch_one(&self.db)
+                        .timed("influx_newest_id")
+                        .await?;
+                let cursor = ExportCursor {
+                    exported_until: Utc::now().naive_utc(),
+                    exported_id: 0,
+                    backfill_exported_id: newest_id.unwrap_or_default(),
+                };
+                self.save(cursor.exported_until, cursor.exported_id).await?;
+                self.save_backfill(cursor.backfill_exported_id).await?;
+                cursor
+            }
+        };
+
+        let written = self
+            .export_after(Some((cursor.exported_until, cursor.exported_id)))
+            .await?;
+        let backfilled = self.export_backfilled(cursor.backfill_exported_id).await?;
+
+        Ok(written + backfilled)
+    }
+
+    /// Pages through readings in `(time, id)` order, so readings sharing the
+    /// time a page ends on aren't skipped. Readings stored before ids were
+    /// added sort as id 0.
+    async fn export_after(
+        &self,
+        mut after: Option<(NaiveDateTime, i64)>,
+    ) -> Result<usize, InfluxError> {
+        let mut written = 0;
+
+        loop {
+            let samples: Vec<Sample> = sqlx::query_as(
+                r#"SELECT coalesce(id, 0) AS id, time, station_id, current_kwh, uv_level, temperature,
+                          raw_data->'data'->'kpi' AS kpi
+                   FROM solar_data_tsdb
+                   WHERE ($1::timestamp IS NULL OR (time, coalesce(id, 0)) > ($1, $2))
+                   ORDER BY time, coalesce(id, 0)
+                   LIMIT $3"#,
+            )
+            .bind(after.map(|(time, _)| time))
+            .bind(after.map_or(0, |(_, id)| id))
+            .bind(self.config.batch_size)
+            .fetch_all(&self.db)
+            .timed("influx_samples")
+            .await?;
+
+            let Some(last) = samples.last() else {
+                break;
+            };
+
+            self.write(&samples).await?;
+            self.save(last.time, last.id).await?;
+            tracing::info!(
+                "wrote {} samples to influxdb up to {}",
+                samples.len(),
+                last.time
+            );
+
+            written += samples.len();
+            after = Some((last.time, last.id));
+
+            if (samples.len() as i64) < self.config.batch_size {
+                break;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Backfilled readings are usually older than the time cursor, so they're
+    /// followed separately in insertion order. Those backfilled before ids
+    /// were added are left to `replay`.
+    async fn export_backfilled(&self, mut after_id: i64) -> Result<usize, InfluxError> {
+        let mut written = 0;
+
+        loop {
+            let samples: Vec<Sample> = sqlx::query_as(
+                r#"SELECT id, time, station_id, current_kwh, uv_level, temperature,
+                          raw_data->'data'->'kpi' AS kpi
+                   FROM solar_data_tsdb
+                   WHERE backfilled AND id > $1
+                   ORDER BY id
+                   LIMIT $2"#,
+            )
+            .bind(after_id)
+            .bind(self.config.batch_size)
+            .fetch_all(&self.db)
+            .timed("influx_backfilled_samples")
+            .await?;
+
+            let Some(last) = samples.last() else {
+                break;
+            };
+
+            self.write(&samples).await?;
+            self.save_backfill(last.id).await?;
+            tracing::info!("wrote {} backfilled samples to influxdb", samples.len());
+
+            written += samples.len();
+            after_id = last.id;
+
+            if (samples.len() as i64) < self.config.batch_size {
+                break;
+            }
+        }
+
+        Ok(written)
+    }
+
+    async fn save(&self, until: NaiveDateTime, id: i64) -> Result<(), InfluxError> {
+        sqlx::query(
+            r#"INSERT INTO influx_state (exported_until, exported_id) VALUES ($1, $2)
+               ON CONFLICT (id) DO UPDATE
+               SET exported_until = $1, exported_id = $2, updated_at = now()
+               WHERE (influx_state.exported_until, influx_state.exported_id) < ($1, $2)"#,
+        )
+        .bind(until)
+        .bind(id)
+        .execute(&self.db)
+        .timed("influx_exported_until")
+        .await?;
+
+        Ok(())
+    }
+
+    async fn save_backfill(&self, id: i64) -> Result<(), InfluxError> {
+        sqlx::query(
+            r#"UPDATE influx_state
+               SET backfill_exported_id = GREATEST(backfill_exported_id, $1), updated_at = now()"#,
+        )
+        .bind(id)
+        .execute(&self.db)
+        .timed("influx_backfill_exported_id")
+        .await?;
+
+        Ok(())
+    }
+
+    async fn write(&self, samples: &[Sample]) -> Result<(), InfluxError> {
+        let body = samples
+            .iter()
+            .map(|sample| sample.line(&self.config.measurement))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let started = Instant::now();
+        let result = self.send(body).await;
+        metrics().record_upstream(Upstream::Influx, started.elapsed(), &result);
+
+        result
+    }
+
+    async fn send(&self, body: String) -> Result<(), InfluxError> {
+        let response = self
+            .http_client
+            .post(self.write_url.clone())
+            .header("Authorization", format!("Token {}", self.config.token))
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(InfluxError::Rejected { status, body });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use chrono::NaiveDateTime;
+use serde_json::{Map, Value};
+use sqlx::{prelude::FromRow, types::Json};
+
+/// How far the export has got through `solar_data_tsdb`.
+#[derive(Debug, Clone, FromRow)]
+pub struct ExportCursor {
+    pub exported_until: NaiveDateTime,
+    pub exported_id: i64,
+    pub backfill_exported_id: i64,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Sample {
+    pub id: i64,
+    pub time: NaiveDateTime,
+    pub station_id: Option<String>,
+    pub current_kwh: f64,
+    pub uv_level: Option<f64>,
+    pub temperature: Option<f64>,
+    /// Missing for backfilled readings, which carry no SEMS totals.
+    pub kpi: Option<Json<Map<String, Value>>>,
+}
+
+impl Sample {
+    /// Formats the sample as a line protocol point with a millisecond timestamp.
+    /// Every numeric KPI field from the SEMS response is included as is.
+    pub fn line(&self, measurement: &str) -> String {
+        let mut line = escape(measurement, &[',', ' ']);
+        if let Some(station_id) = &self.station_id {
+            line.push_str(",station_id=");
+            line.push_str(&escape(station_id, &[',', '=', ' ']));
+        }
+
+        let kpi = self
+            .kpi
+            .iter()
+            .flat_map(|kpi| kpi.iter())
+            .filter_map(|(key, value)| Some((key.as_str(), value.as_f64()?)));
+        let fields = [
+            ("current_kwh", Some(self.current_kwh)),
+            ("uv_level", self.uv_level),
+            ("temperature", self.temperature),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .chain(kpi)
+        .filter(|(_, value)| value.is_finite());
+
+        for (i, (key, value)) in fields.enumerate() {
+            line.push(if i == 0 { ' ' } else { ',' });
+            line.push_str(&escape(key, &[',', '=', ' ']));
+            line.push('=');
+            line.push_str(&value.to_string());
+        }
+
+        line.push(' ');
+        line.push_str(&self.time.and_utc().timestamp_millis().to_string());
+        line
+    }
+}
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
@@ -0,0 +1,149 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+use types::{LeaderStatus, Lease};
+
+use crate::{config::types::LeaderConfig, metrics::Timed};
+
+pub mod types;
+
+/// Elects one replica to poll and push to sinks, through a lease row that the
+/// leader keeps renewing. A replica takes over once the lease expires.
+#[derive(Clone)]
+pub struct LeaderElection {
+    db: PgPool,
+    config: LeaderConfig,
+    instance_id: Arc<str>,
+    /// When this replica must stop acting as leader, measured from before the
+    /// last renewal so it always steps down before the lease can be taken.
+    deadline: Arc<Mutex<Option<Instant>>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LeaderError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl LeaderElection {
+    const LEASE: &str = "poller";
+
+    pub fn new(db: PgPool, config: LeaderConfig) -> Self {
+        let instance_id = config.instance_id.clone().unwrap_or_else(|| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "solar".to_string());
+            format!("{host}-{}", std::process::id())
+        });
+
+        Self {
+            db,
+            config,
+            instance_id: instance_id.into(),
+            deadline: Arc::default(),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.deadline
+            .lock()
+            .unwrap()
+            .is_some_and(|deadline| Instant::now() < deadline)
+    }
+
+    /// Takes or renews the lease until `shutdown`. The lease is kept after
+    /// this returns so work still in progress stays covered; see `release`.
+    pub async fn run(&self, shutdown: CancellationToken) {
+        tracing::info!("competing for the lease as {}", self.instance_id);
+
+        loop {
+            let was_leader = self.is_leader();
+            match self.renew().await {
+                Ok(leader) => {
+                    if leader && !was_leader {
+                        tracing::info!("{} is now the leader", self.instance_id);
+                    } else if !leader && was_leader {
+                        tracing::warn!("{} lost the lease", self.instance_id);
+                    }
+                }
+                // the deadline is left alone, so leadership lapses on its own
+                // if the database stays unreachable
+                Err(e) => tracing::error!("error renewing lease: {e}"),
+            }
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(Duration::from_secs(self.config.renew_secs)) => {}
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn renew(&self) -> Result<bool, LeaderError> {
+        let started = Instant::now();
+        let holder: Option<String> = sqlx::query_scalar(
+            r#"INSERT INTO leases (name, holder, acquired_at, renewed_at, expires_at)
+               VALUES ($1, $2, now(), now(), now() + make_interval(secs => $3))
+               ON CONFLICT (name) DO UPDATE
+               SET holder = EXCLUDED.holder,
+                   acquired_at = CASE WHEN leases.holder = EXCLUDED.holder
+                                      THEN leases.acquired_at ELSE now() END,
+                   renewed_at = now(),
+                   expires_at = EXCLUDED.expires_at
+               WHERE leases.holder = EXCLUDED.holder OR leases.expires_at < now()
+               RETURNING holder"#,
+        )
+        .bind(Self::LEASE)
+        .bind(&*self.instance_id)
+        .bind(self.config.lease_secs as f64)
+        .fetch_optional(&self.db)
+        .timed("renew_lease")
+        .await?;
+
+        let leader = holder.is_some();
+        *self.deadline.lock().unwrap() =
+            leader.then(|| started + Duration::from_secs(self.config.lease_secs));
+
+        Ok(leader)
+    }
+
+    /// Gives up the lease, if held, so another replica can take over without
+    /// waiting for it to expire.
+    pub async fn release(&self) {
+        *self.deadline.lock().unwrap() = None;
+
+        let result = sqlx::query("DELETE FROM leases WHERE name = $1 AND holder = $2")
+            .bind(Self::LEASE)
+            .bind(&*self.instance_id)
+            .execute(&self.db)
+            .timed("release_lease")
+            .await;
+
+        match result {
+            Ok(done) if done.rows_affected() > 0 => {
+                tracing::info!("{} released the lease", self.instance_id)
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("error releasing lease: {e}"),
+        }
+    }
+
+    pub async fn status(&self) -> Result<LeaderStatus, LeaderError> {
+        let lease: Option<Lease> = sqlx::query_as(
+            "SELECT holder, acquired_at, renewed_at, expires_at FROM leases WHERE name = $1",
+        )
+        .bind(Self::LEASE)
+        .fetch_optional(&self.db)
+        .timed("lease_status")
+        .await?;
+
+        Ok(LeaderStatus {
+            instance_id: self.instance_id.to_string(),
+            is_leader: self.is_leader(),
+            lease,
+        })
+    }
+}
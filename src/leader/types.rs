@@ -0,0 +1,22 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+
+/// The row in `leases`, with times in UTC.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Lease {
+    pub holder: String,
+    pub acquired_at: NaiveDateTime,
+    pub renewed_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderStatus {
+    pub instance_id: String,
+    pub is_leader: bool,
+    /// `None` until a replica first takes the lease.
+    pub lease: Option<Lease>,
+}
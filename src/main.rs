@@ -1,50 +1,73 @@
+use alerts::AlertManager;
+use anomaly::AnomalyDetector;
+use anyhow::Context;
 use axum::{
     Json,
-    extract::{Query, State},
-    http::StatusCode,
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
     routing::get,
 };
 use axum_tracing_opentelemetry::middleware::OtelAxumLayer;
-use background::BackgroundTask;
-use chrono::{FixedOffset, NaiveDateTime};
-use goodwe::{GoodWeSemsAPI, types::PlantDetailsByPowerStationIdResponse};
+use background::{BackgroundTask, schedule::PollSchedule};
+use chrono::{FixedOffset, NaiveDateTime, Utc};
+use clap::Parser;
+use cli::{Cli, Command};
+use config::types::Config;
+use goodwe::GoodWeSemsAPI;
+use health::{HealthCheck, types::HealthStatus};
+use influx::InfluxSink;
+use leader::LeaderElection;
+use metrics::{Timed, Upstream};
+use mqtt::MqttPublisher;
+use outbox::Outbox;
+use pvoutput::PvOutput;
+use quality::{DataQuality, QualityError, types::DataQualityReport};
 use reqwest::Method;
+use retention::Retention;
 use serde::Deserialize;
-use sqlx::{postgres::PgPoolOptions, prelude::FromRow};
+use sqlx::{PgPool, postgres::PgPoolOptions, prelude::FromRow};
 use std::{future::IntoFuture, ops::Deref, sync::Arc};
+use subscriptions::Subscriptions;
+use summary::SummaryService;
 use tokio_cron_scheduler::{JobBuilder, JobScheduler};
+use tokio_util::sync::CancellationToken;
 use tower::limit::GlobalConcurrencyLimitLayer;
 use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 use tracing::Instrument;
-use twilight_cache_inmemory::{DefaultCacheModels, InMemoryCacheBuilder, ResourceType};
-use twilight_gateway::{
-    ConfigBuilder, Event, EventType, EventTypeFlags, Intents, Shard, ShardId, StreamExt,
-};
-use twilight_http::{Client as HttpClient, client::InteractionClient};
-use twilight_model::{
-    application::{
-        command::CommandType,
-        interaction::{Interaction, InteractionContextType, InteractionData, InteractionType},
-    },
-    http::interaction::{InteractionResponse, InteractionResponseType},
-    id::{Id, marker::ApplicationMarker},
-    oauth::ApplicationIntegrationType,
-};
-use twilight_util::builder::{
-    command::CommandBuilder,
-    embed::{EmbedBuilder, EmbedFieldBuilder},
-};
+use twilight_http::Client as HttpClient;
 use types::{
-    AppError, GenerationHistory, SolarCurrentResponse, SolarCurrentStatistics,
-    SolarCurrentStatisticsAverages, SolarHistoryResponse, SolarHistoryV2Response,
+    AnomaliesResponse, ApiQuery, AppError, GenerationHistory, SolarCurrentResponse,
+    SolarCurrentStatistics, SolarCurrentStatisticsAverages, SolarHistoryResponse,
+    SolarHistoryV2Response,
 };
 use weather::WeatherAPI;
+use webhooks::Webhooks;
 
+mod alerts;
+mod anomaly;
 mod background;
+mod chart;
+mod cli;
+mod config;
+mod discord;
 mod goodwe;
+mod health;
+mod influx;
+mod leader;
+mod metrics;
+mod mqtt;
+mod outbox;
+mod pvoutput;
+mod quality;
+mod retention;
+mod subscriptions;
+mod summary;
+mod sun;
 mod tracing_setup;
 mod types;
 mod weather;
+mod webhooks;
 
 #[derive(Clone)]
 struct BotContext(Arc<BotContextInner>);
@@ -59,183 +82,16 @@ impl Deref for BotContext {
 
 struct BotContextInner {
     solar_api: GoodWeSemsAPI,
-}
-
-async fn handle_event(event: Event, _http: Arc<HttpClient>) -> anyhow::Result<()> {
-    match event {
-        Event::GatewayHeartbeatAck
-        | Event::MessageCreate(_)
-        | Event::MessageUpdate(_)
-        | Event::MessageDelete(_) => {}
-        // Other events here...
-        e => {
-            tracing::warn!("unhandled event: {e:?}")
-        }
-    }
-
-    Ok(())
-}
-
-async fn handle_interaction_error(
-    interaction: &Interaction,
-    interaction_client: &InteractionClient<'_>,
-    error: anyhow::Error,
-) {
-    let fut = async {
-        let error = if error.to_string().contains("Missing Access") {
-            "This channel is not accessible to the bot...".to_string()
-        } else {
-            error.to_string()
-        };
-
-        let embed = EmbedBuilder::new()
-            .title("oops")
-            .description(error)
-            .color(0xcc6666)
-            .validate()?
-            .build();
-
-        interaction_client
-            .update_response(&interaction.token)
-            .embeds(Some(&[embed]))
-            .await?;
-
-        Ok::<(), anyhow::Error>(())
-    };
-
-    if let Err(e) = fut.await {
-        tracing::error!("error in updating message: {e:?}");
-    }
-
-    tracing::error!("error in interaction: {error:?}");
-}
-
-/// Dispatches an incoming interaction to the matching command handler,
-/// routing any error to the shared error handler.
-async fn process_interaction(
-    interaction: Interaction,
-    http: Arc<HttpClient>,
-    app_id: Id<ApplicationMarker>,
-    context: BotContext,
-) {
-    if interaction.kind != InteractionType::ApplicationCommand {
-        return;
-    }
-
-    let Some(InteractionData::ApplicationCommand(data)) = &interaction.data else {
-        return;
-    };
-
-    let interaction_client = http.interaction(app_id);
-
-    match data.name.as_str() {
-        "solar" => {
-            if let Err(error) = solar(&interaction, &interaction_client, &context).await {
-                handle_interaction_error(&interaction, &interaction_client, error).await;
-            }
-        }
-        other => tracing::warn!("unhandled command: {other}"),
-    }
-}
-
-async fn solar(
-    interaction: &Interaction,
-    interaction_client: &InteractionClient<'_>,
-    context: &BotContext,
-) -> anyhow::Result<()> {
-    interaction_client
-        .create_response(
-            interaction.id,
-            &interaction.token,
-            &InteractionResponse {
-                kind: InteractionResponseType::DeferredChannelMessageWithSource,
-                data: None,
-            },
-        )
-        .await?;
-
-    let solar_data = context.solar_api.get_latest_saved_solar_data().await?;
-    let SolarCurrentStatistics { averages } = solar_statistics(&context.solar_api).await?;
-
-    let embed = EmbedBuilder::new()
-        .title("Solar panels")
-        .field(
-            EmbedFieldBuilder::new(
-                "Current",
-                format!("{} Wh", solar_data.raw_data.data.kpi.pac),
-            )
-            .inline(),
-        )
-        .field(
-            EmbedFieldBuilder::new(
-                "UV Level",
-                solar_data
-                    .uv_level
-                    .map(|uv| uv.to_string())
-                    .unwrap_or("unknown".to_string()),
-            )
-            .inline(),
-        )
-        .field(
-            EmbedFieldBuilder::new(
-                "Total for today",
-                format!("{} kWh", solar_data.raw_data.data.kpi.power),
-            )
-            .inline(),
-        )
-        .field(
-            EmbedFieldBuilder::new(
-                "15 min avg",
-                format!(
-                    "{} Wh",
-                    averages
-                        .last_15_mins
-                        .map(|r| r.to_string())
-                        .unwrap_or("unknown".to_string())
-                ),
-            )
-            .inline(),
-        )
-        .field(
-            EmbedFieldBuilder::new(
-                "1 hour avg",
-                format!(
-                    "{} Wh",
-                    averages
-                        .last_1_hour
-                        .map(|r| r.to_string())
-                        .unwrap_or("unknown".to_string())
-                ),
-            )
-            .inline(),
-        )
-        .field(
-            EmbedFieldBuilder::new(
-                "3 hour avg",
-                format!(
-                    "{} Wh",
-                    averages
-                        .last_3_hours
-                        .map(|r| r.to_string())
-                        .unwrap_or("unknown".to_string())
-                ),
-            )
-            .inline(),
-        )
-        .color(0x40944c)
-        .validate()?
-        .build();
-
-    interaction_client
-        .update_response(&interaction.token)
-        .embeds(Some(&[embed]))
-        .await?;
-
-    Ok(())
+    anomaly_detector: AnomalyDetector,
+    summary: SummaryService,
+    subscriptions: Subscriptions,
+    health: HealthCheck,
+    quality: DataQuality,
 }
 
 pub async fn get_average_for_last_n_minutes(
     s: i32,
+    station: Option<&str>,
     solar_api: &GoodWeSemsAPI,
 ) -> Result<Option<f64>, anyhow::Error> {
     #[derive(FromRow)]
@@ -245,10 +101,14 @@ pub async fn get_average_for_last_n_minutes(
 
     let avg_row: Option<Row> = sqlx::query_as(r#"SELECT avg(current_kwh)
                                                  FROM solar_data_tsdb
-                                                 WHERE (time + '8 hour') > ((NOW() + '8 hour') - MAKE_INTERVAL(mins => $1))"#)
+                                                 WHERE (time + '8 hour') > ((NOW() + '8 hour') - MAKE_INTERVAL(mins => $1))
+                                                   AND ($2::text IS NULL OR coalesce(station_id, $3) = $2)"#)
         .bind(s)
+        .bind(station)
+        .bind(solar_api.powerstation_id())
         .fetch_optional(solar_api.db())
         .instrument(tracing::info_span!("solar_average", time_in_mins = s))
+        .timed("solar_average")
         .await?;
 
     Ok(avg_row.and_then(|r| r.avg))
@@ -259,11 +119,12 @@ fn round(n: Option<f64>) -> Option<f64> {
 }
 
 pub async fn solar_statistics(
+    station: Option<&str>,
     solar_api: &GoodWeSemsAPI,
 ) -> Result<SolarCurrentStatistics, anyhow::Error> {
-    let avg_15_mins = get_average_for_last_n_minutes(15, solar_api);
-    let avg_1_hour = get_average_for_last_n_minutes(60, solar_api);
-    let avg_3_hours = get_average_for_last_n_minutes(180, solar_api);
+    let avg_15_mins = get_average_for_last_n_minutes(15, station, solar_api);
+    let avg_1_hour = get_average_for_last_n_minutes(60, station, solar_api);
+    let avg_3_hours = get_average_for_last_n_minutes(180, station, solar_api);
 
     let (avg_15_mins, avg_1_hour, avg_3_hours) =
         futures::try_join!(avg_15_mins, avg_1_hour, avg_3_hours)?;
@@ -280,32 +141,28 @@ pub async fn solar_statistics(
 async fn solar_current(
     State(ctx): State<BotContext>,
 ) -> Result<Json<SolarCurrentResponse>, AppError> {
-    let resp = ctx.solar_api.get_latest_saved_solar_data().await?;
+    let resp = ctx
+        .solar_api
+        .get_latest_saved_solar_data(None)
+        .await?
+        .ok_or_else(|| AppError::NotFound("no readings have been saved yet".to_string()))?;
     let raw_data = resp.raw_data;
-    let yesterday_results = sqlx::query!(
-        "SELECT raw_data FROM solar_data_tsdb WHERE (time + '8 hour')::date = (now() + '8 hour')::date - INTEGER '1' ORDER BY time DESC LIMIT 1"
-    )
-    .fetch_optional(ctx.solar_api.db())
-    .instrument(tracing::info_span!("get_yesterday_results"))
-    .await?;
-
-    let yesterday_value = if let Some(data) = yesterday_results {
-        Some(serde_json::from_value::<
-            PlantDetailsByPowerStationIdResponse,
-        >(data.raw_data)?)
-    } else {
-        None
-    };
+    let yesterday = Utc::now()
+        .with_timezone(&chrono_tz::Australia::Perth)
+        .date_naive()
+        - chrono::Duration::days(1);
+    // falls back to integrating the readings when yesterday was backfilled
+    let yesterday_summary = ctx.summary.compute(yesterday, None).await?;
 
     Ok(Json(SolarCurrentResponse {
-        yesterday_production_kwh: yesterday_value.map(|d| d.data.kpi.power).unwrap_or(0f64),
+        yesterday_production_kwh: yesterday_summary.map(|d| d.total_kwh).unwrap_or(0f64),
         month_production_kwh: raw_data.data.kpi.month_generation,
         current_production_wh: raw_data.data.kpi.pac,
         today_production_kwh: raw_data.data.kpi.power,
         all_time_production_kwh: raw_data.data.kpi.total_power,
         uv_level: resp.uv_level,
         temperature: resp.temperature,
-        statistics: solar_statistics(&ctx.solar_api).await?,
+        statistics: solar_statistics(None, &ctx.solar_api).await?,
     }))
 }
 
@@ -316,13 +173,14 @@ struct SolarHistoryQueryParams {
 
 async fn solar_history_with_query(
     State(ctx): State<BotContext>,
-    params: Query<SolarHistoryQueryParams>,
+    ApiQuery(params): ApiQuery<SolarHistoryQueryParams>,
 ) -> Result<Json<SolarHistoryV2Response>, AppError> {
     let history: Vec<_> = sqlx::query!(
         "SELECT avg(current_kwh) as avg_wh, avg(uv_level) as avg_uv_level, avg(temperature) as avg_temp, time_bucket('5 minutes', time) as bucket_time FROM solar_data_tsdb WHERE time >= $1 GROUP BY bucket_time ORDER BY bucket_time ASC", params.since
     )
     .fetch_all(ctx.solar_api.db())
     .instrument(tracing::info_span!("history_with_query"))
+    .timed("history_with_query")
     .await?
     .into_iter()
     .map(|r| {
@@ -350,6 +208,7 @@ async fn solar_history(
         "SELECT avg(current_kwh) as avg_wh, avg(uv_level) as avg_uv_level, avg(temperature) as avg_temp, time_bucket('5 minutes', time) as bucket_time FROM solar_data_tsdb WHERE (time + '8 hour')::date > ((NOW() + '8 hour')::date - 2) GROUP BY bucket_time ORDER BY bucket_time ASC"
     )
     .fetch_all(ctx.solar_api.db())
+    .timed("history")
     .await?
     .into_iter()
     .map(|r| {
@@ -373,143 +232,473 @@ async fn solar_history(
     Ok(Json(SolarHistoryResponse { today, yesterday }))
 }
 
-async fn health(_ctx: State<BotContext>) -> StatusCode {
-    StatusCode::NO_CONTENT
+#[derive(Deserialize)]
+struct AnomaliesQueryParams {
+    since: Option<NaiveDateTime>,
+    #[serde(default)]
+    open: bool,
+}
+
+async fn anomalies(
+    State(ctx): State<BotContext>,
+    ApiQuery(params): ApiQuery<AnomaliesQueryParams>,
+) -> Result<Json<AnomaliesResponse>, AppError> {
+    let anomalies = ctx.anomaly_detector.list(params.since, params.open).await?;
+
+    Ok(Json(AnomaliesResponse { anomalies }))
+}
+
+#[derive(Deserialize)]
+struct DataQualityQueryParams {
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+    #[serde(default)]
+    backfill: bool,
+}
+
+/// Defaults to the last 24 hours.
+async fn data_quality(
+    State(ctx): State<BotContext>,
+    ApiQuery(params): ApiQuery<DataQualityQueryParams>,
+) -> Result<Json<DataQualityReport>, AppError> {
+    if params.backfill && !ctx.quality.api_backfill() {
+        return Err(AppError::Forbidden(
+            "backfill through the api is disabled".to_string(),
+        ));
+    }
+
+    let to = params.to.unwrap_or_else(|| Utc::now().naive_utc());
+    let from = params.from.unwrap_or(to - chrono::Duration::days(1));
+
+    match ctx.quality.scan(from, to, params.backfill).await {
+        Ok(report) => Ok(Json(report)),
+        Err(e @ QualityError::InvalidRange(_)) => Err(AppError::BadRequest(e.to_string())),
+        Err(QualityError::SolarAPI(e)) => Err(AppError::UpstreamUnavailable {
+            upstream: Upstream::Sems,
+            source: e.into(),
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn prometheus_metrics(
+    State(ctx): State<BotContext>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), AppError> {
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::prometheus::render(ctx.solar_api.db()).await?,
+    ))
+}
+
+async fn health(State(ctx): State<BotContext>) -> Result<Response, AppError> {
+    let report = ctx.health.check().await?;
+    let status = match report.status {
+        HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Ok | HealthStatus::Degraded => StatusCode::OK,
+    };
+
+    Ok((status, Json(report)).into_response())
+}
+
+async fn not_found() -> AppError {
+    AppError::NotFound("no such route".to_string())
+}
+
+/// Resolves on ctrl-c or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("error listening for ctrl-c: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("error listening for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// The services shared by the server and the maintenance commands.
+struct Services {
+    solar_api: GoodWeSemsAPI,
+    weather_api: WeatherAPI,
+    anomaly_detector: AnomalyDetector,
+    subscriptions: Subscriptions,
+    summary: SummaryService,
+    background: BackgroundTask,
+    outbox: Outbox,
+    health: HealthCheck,
+    leader: LeaderElection,
+    webhooks: Webhooks,
+    mqtt: Option<MqttPublisher>,
+    pvoutput: Option<PvOutput>,
+    influx: Option<InfluxSink>,
+    quality: DataQuality,
+    retention: Retention,
+    /// `None` when the Discord integration is disabled.
+    http: Option<Arc<HttpClient>>,
+}
+
+impl Services {
+    /// `ingests` is set for the commands that poll, which are the only ones
+    /// that open an MQTT connection.
+    fn new(config: &Config, pool: PgPool, ingests: bool) -> Self {
+        let solar_api = GoodWeSemsAPI::new(
+            pool.clone(),
+            config.goodwe.username.clone(),
+            config.goodwe.password.clone(),
+            config.goodwe.powerstation_id.clone(),
+        );
+
+        let weather_api = WeatherAPI::new();
+        let anomaly_detector = AnomalyDetector::new(pool.clone());
+        let http = config
+            .discord
+            .token()
+            .map(|token| Arc::new(HttpClient::new(token.to_owned())));
+        let subscriptions = Subscriptions::new(pool.clone(), http.clone());
+        let webhooks = Webhooks::new(pool.clone(), config.webhooks.clone());
+        let alert_manager = AlertManager::new(
+            pool.clone(),
+            http.clone(),
+            config.alerts.clone(),
+            subscriptions.clone(),
+            webhooks.clone(),
+        );
+        let summary = SummaryService::new(
+            pool.clone(),
+            http.clone(),
+            config.summary.channel_id,
+            config.goodwe.powerstation_id.clone(),
+            subscriptions.clone(),
+            weather_api.clone(),
+            webhooks.clone(),
+        );
+        let leader = LeaderElection::new(pool.clone(), config.leader.clone());
+        let mqtt = config.mqtt.host.as_deref().filter(|_| ingests).map(|host| {
+            MqttPublisher::new(
+                &config.mqtt,
+                host,
+                &config.goodwe.powerstation_id,
+                leader.clone(),
+            )
+        });
+        let pvoutput = PvOutput::new(pool.clone(), config.pvoutput.clone(), summary.clone());
+        let influx = InfluxSink::new(pool.clone(), config.influx.clone());
+        let outbox = Outbox::new(
+            pool.clone(),
+            config.outbox.clone(),
+            config.home_gateway.clone(),
+            webhooks.clone(),
+        );
+        let schedule = PollSchedule::new(&config.poller);
+        let health = HealthCheck::new(
+            pool.clone(),
+            config.health.clone(),
+            outbox.clone(),
+            leader.clone(),
+            schedule.clone(),
+        );
+        let retention = Retention::new(pool.clone(), config.retention.clone());
+        let quality = DataQuality::new(config.quality.clone(), solar_api.clone(), schedule.clone());
+        let background = BackgroundTask::new(
+            pool,
+            solar_api.clone(),
+            weather_api.clone(),
+            anomaly_detector.clone(),
+            alert_manager,
+            config.home_gateway.clone(),
+            mqtt.clone(),
+            webhooks.clone(),
+            config.poller.clone(),
+        );
+
+        Self {
+            solar_api,
+            weather_api,
+            anomaly_detector,
+            subscriptions,
+            summary,
+            background,
+            outbox,
+            health,
+            leader,
+            webhooks,
+            mqtt,
+            pvoutput,
+            influx,
+            quality,
+            retention,
+            http,
+        }
+    }
+
+    /// Flushes connections that buffer outgoing messages.
+    async fn close(&self) {
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.disconnect().await;
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     rustls::crypto::aws_lc_rs::default_provider()
         .install_default()
         .expect("failed to install default crypto provider");
 
-    tracing_setup::init();
+    let config = Config::load()?;
+    let telemetry = tracing_setup::init(&config.telemetry)?;
+
+    let result = run(cli.command.unwrap_or_default(), config).await;
 
-    let database_url = std::env::var("DATABASE_URL")?;
-    let token = std::env::var("DISCORD_TOKEN")?;
-    let goodwe_username = std::env::var("GOODWE_API_USERNAME")?;
-    let goodwe_password = std::env::var("GOODWE_API_PASSWORD")?;
-    let goodwe_powerstation_id = std::env::var("GOODWE_API_POWERSTATION_ID")?;
+    telemetry.shutdown();
 
+    result
+}
+
+async fn run(command: Command, config: Config) -> anyhow::Result<()> {
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(config.database.max_connections)
+        .connect(&config.database.url)
         .await?;
 
+    let ingests = matches!(command, Command::Serve | Command::PollOnce);
+    let services = Services::new(&config, pool.clone(), ingests);
+
+    match command {
+        Command::Serve => serve(config, pool, services).await,
+        Command::PollOnce => {
+            let result = cli::poll_once(&services.background).await;
+            services.close().await;
+            result
+        }
+        Command::Migrate => cli::migrate(&pool).await,
+        Command::Backfill { from, to } => cli::backfill(&services.solar_api, from, to).await,
+        Command::Export {
+            format,
+            from,
+            to,
+            output,
+        } => cli::export(&pool, format, from, to, output).await,
+        Command::CheckCredentials => {
+            cli::check_credentials(&services.solar_api, &services.weather_api).await
+        }
+        Command::RecomputeRollups { from, to } => {
+            cli::recompute_rollups(&pool, &services.summary, from, to).await
+        }
+        Command::DataQuality { from, to, backfill } => {
+            cli::data_quality(&services.quality, from, to, backfill).await
+        }
+        Command::InfluxReplay => cli::influx_replay(services.influx.as_ref()).await,
+        Command::WebhookDeliveries { name, limit } => {
+            cli::webhook_deliveries(&services.webhooks, name.as_deref(), limit).await
+        }
+    }
+}
+
+async fn serve(config: Config, pool: PgPool, services: Services) -> anyhow::Result<()> {
     sqlx::migrate!("./migrations").run(&pool).await?;
 
-    let solar_api = GoodWeSemsAPI::new(
-        pool.clone(),
-        goodwe_username,
-        goodwe_password,
-        goodwe_powerstation_id,
-    );
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("shutdown signal received");
+            shutdown.cancel();
+        }
+    });
 
-    let weather_api = WeatherAPI::new();
+    let leader_worker = tokio::spawn({
+        let leader = services.leader.clone();
+        let shutdown = shutdown.clone();
+        async move { leader.run(shutdown).await }
+    });
 
-    let sched = JobScheduler::new().await?;
-    let bg_task = BackgroundTask::new(pool, solar_api.clone(), weather_api);
+    let mut sched = JobScheduler::new().await?;
+    let bg_task = services.background.clone();
+    let leader = services.leader.clone();
     let job = JobBuilder::new()
         .with_timezone(chrono_tz::Australia::Perth)
         .with_cron_job_type()
-        .with_schedule("every 1 minute")?
+        .with_schedule(config.poller.schedule.as_str())
+        .context("invalid poller.schedule")?
         .with_run_async(Box::new(move |uuid, mut _l| {
             tracing::info!("running bg task: {uuid}");
             let bg_task = bg_task.clone();
-            Box::pin(async move { bg_task.run_task().await })
+            let leader = leader.clone();
+            Box::pin(async move {
+                if !leader.is_leader() {
+                    tracing::debug!("not the leader, skipping poll");
+                    return;
+                }
+
+                bg_task.run_scheduled().await;
+            })
+        }))
+        .build()?;
+
+    let summary_task = services.summary.clone();
+    let leader = services.leader.clone();
+    let summary_job = JobBuilder::new()
+        .with_timezone(chrono_tz::Australia::Perth)
+        .with_cron_job_type()
+        .with_schedule(config.poller.summary_schedule.as_str())
+        .context("invalid poller.summary_schedule")?
+        .with_run_async(Box::new(move |uuid, mut _l| {
+            tracing::info!("running summary task: {uuid}");
+            let summary_task = summary_task.clone();
+            let leader = leader.clone();
+            Box::pin(async move {
+                if leader.is_leader() {
+                    summary_task.run_task().await;
+                }
+            })
+        }))
+        .build()?;
+
+    let retention = services.retention.clone();
+    let leader = services.leader.clone();
+    let retention_job = JobBuilder::new()
+        .with_timezone(chrono_tz::Australia::Perth)
+        .with_cron_job_type()
+        .with_schedule(config.retention.schedule.as_str())
+        .context("invalid retention.schedule")?
+        .with_run_async(Box::new(move |_uuid, mut _l| {
+            let retention = retention.clone();
+            let leader = leader.clone();
+            Box::pin(async move {
+                if leader.is_leader() {
+                    retention.run_task().await;
+                }
+            })
         }))
         .build()?;
 
     sched.add(job).await?;
+    sched.add(summary_job).await?;
+    sched.add(retention_job).await?;
     sched.start().await?;
 
-    let context = BotContext(BotContextInner { solar_api }.into());
-
-    let config = ConfigBuilder::new(token.clone(), Intents::GUILD_MESSAGES).build();
-    let mut shard = Shard::with_config(ShardId::ONE, config);
-    let http = Arc::new(HttpClient::new(token));
-    let cache = InMemoryCacheBuilder::<DefaultCacheModels>::new()
-        .resource_types(ResourceType::MESSAGE | ResourceType::GUILD)
-        .build();
+    let context = BotContext(
+        BotContextInner {
+            solar_api: services.solar_api.clone(),
+            anomaly_detector: services.anomaly_detector.clone(),
+            summary: services.summary.clone(),
+            subscriptions: services.subscriptions.clone(),
+            health: services.health.clone(),
+            quality: services.quality.clone(),
+        }
+        .into(),
+    );
 
     let routes = axum::Router::new()
         .route("/current", get(solar_current))
         .route("/history", get(solar_history))
-        .route("/v2/history", get(solar_history_with_query));
+        .route("/v2/history", get(solar_history_with_query))
+        .route("/anomalies", get(anomalies))
+        .route("/data-quality", get(data_quality));
 
     let app = axum::Router::new()
         .nest("/api", routes)
         .layer(
             CorsLayer::new()
-                .allow_origin(AllowOrigin::list([
-                    "http://localhost:5173".parse()?,
-                    "https://solar-panels.anurag.sh".parse()?,
-                ]))
+                .allow_origin(AllowOrigin::list(
+                    config
+                        .http
+                        .cors_origins
+                        .iter()
+                        .map(|origin| origin.parse())
+                        .collect::<Result<Vec<_>, _>>()?,
+                ))
                 .allow_methods([Method::GET, Method::OPTIONS])
                 .allow_headers(AllowHeaders::any()),
         )
         .layer(OtelAxumLayer::default())
         .route("/api/health", get(health))
-        .layer(GlobalConcurrencyLimitLayer::new(2048))
+        .route("/metrics", get(prometheus_metrics))
+        .fallback(not_found)
+        .route_layer(axum::middleware::from_fn(metrics::track_requests))
+        .layer(GlobalConcurrencyLimitLayer::new(
+            config.http.concurrency_limit,
+        ))
         .with_state(context.clone());
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
-    tracing::info!("spawning axum");
-    tokio::spawn(axum::serve(listener, app).into_future());
-
-    let app_id = http.current_user_application().await?.model().await?.id;
-    let interaction_client = http.interaction(app_id);
-
-    let solar_command =
-        CommandBuilder::new("solar", "get latest solar details", CommandType::ChatInput)
-            .integration_types(vec![
-                ApplicationIntegrationType::GuildInstall,
-                ApplicationIntegrationType::UserInstall,
-            ])
-            .contexts(vec![
-                InteractionContextType::BotDm,
-                InteractionContextType::PrivateChannel,
-                InteractionContextType::Guild,
-            ])
-            .build();
-
-    tracing::info!("updating commands: 1");
-    interaction_client
-        .set_global_commands(&[solar_command])
-        .await?;
+    let listener = tokio::net::TcpListener::bind(config.http.listen).await?;
 
-    tracing::info!("starting event loop");
-    while let Some(event) = shard.next_event(EventTypeFlags::all()).await {
-        let Ok(event) = event else {
-            let source = event.unwrap_err();
-            tracing::warn!(source = ?source, "error receiving event");
+    let outbox_worker = tokio::spawn({
+        let outbox = services.outbox.clone();
+        let leader = services.leader.clone();
+        let shutdown = shutdown.clone();
+        async move { outbox.run(leader, shutdown).await }
+    });
 
-            continue;
-        };
+    let pvoutput_worker = services.pvoutput.clone().map(|pvoutput| {
+        let leader = services.leader.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { pvoutput.run(leader, shutdown).await })
+    });
 
-        if matches!(event.kind(), EventType::GatewayHeartbeatAck) {
-            continue;
-        }
+    let influx_worker = services.influx.clone().map(|influx| {
+        let leader = services.leader.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { influx.run(leader, shutdown).await })
+    });
 
-        cache.update(&event);
+    tracing::info!("spawning axum");
+    let server = tokio::spawn(
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+            .into_future(),
+    );
 
-        if matches!(event.kind(), EventType::Ready) {
-            tracing::info!("connected on shard");
-            continue;
+    let discord_token = config.discord.token().map(str::to_owned);
+    let discord_result = match discord_token.zip(services.http.clone()) {
+        Some((token, http)) => discord::run(token, http, context, shutdown.clone()).await,
+        None => {
+            tracing::info!("discord disabled, serving api only");
+            shutdown.cancelled().await;
+            Ok(())
         }
+    };
 
-        if let Event::InteractionCreate(i) = event {
-            let http = Arc::clone(&http);
-            let context = context.clone();
-            tokio::spawn(async move {
-                process_interaction(i.0, http, app_id, context).await;
-            });
-
-            continue;
-        }
+    // the gateway can also stop by itself, so make sure everything else does too
+    shutdown.cancel();
+    if let Err(e) = &discord_result {
+        tracing::error!("discord gateway failed: {e:?}");
+    }
 
-        tokio::spawn(handle_event(event, Arc::clone(&http)));
+    tracing::info!("shutting down");
+    sched.shutdown().await?;
+    services.background.wait_idle().await;
+    outbox_worker.await?;
+    if let Some(pvoutput_worker) = pvoutput_worker {
+        pvoutput_worker.await?;
+    }
+    if let Some(influx_worker) = influx_worker {
+        influx_worker.await?;
     }
+    leader_worker.await?;
+    // still the lease holder, so the device can be marked offline
+    services.close().await;
+    services.leader.release().await;
+    server.await??;
 
-    Ok(())
+    discord_result
 }
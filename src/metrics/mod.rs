@@ -0,0 +1,200 @@
+use std::{
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{
+    KeyValue, global,
+    metrics::{Counter, Gauge, Histogram},
+};
+
+pub mod prometheus;
+
+/// The external services the poller and uploaders call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upstream {
+    Sems,
+    Bom,
+    Arpansa,
+    HomeGateway,
+    PvOutput,
+    Influx,
+}
+
+impl Upstream {
+    pub const ALL: [Upstream; 6] = [
+        Upstream::Sems,
+        Upstream::Bom,
+        Upstream::Arpansa,
+        Upstream::HomeGateway,
+        Upstream::PvOutput,
+        Upstream::Influx,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Upstream::Sems => "sems",
+            Upstream::Bom => "bom",
+            Upstream::Arpansa => "arpansa",
+            Upstream::HomeGateway => "home_gateway",
+            Upstream::PvOutput => "pvoutput",
+            Upstream::Influx => "influx",
+        }
+    }
+}
+
+pub struct Metrics {
+    /// Success and failure totals per upstream, kept in-process for the
+    /// Prometheus endpoint.
+    upstream_totals: [[AtomicU64; 2]; Upstream::ALL.len()],
+    production: Gauge<f64>,
+    energy_today: Gauge<f64>,
+    upstream_requests: Counter<u64>,
+    upstream_duration: Histogram<f64>,
+    sems_logins: Counter<u64>,
+    db_query_duration: Histogram<f64>,
+    http_requests: Counter<u64>,
+}
+
+/// Instruments are created from the global meter on first use, which happens
+/// after `tracing_setup::init` has installed the provider. Without an OTLP
+/// endpoint they are no-ops.
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let meter = global::meter("solar-panels");
+
+    Metrics {
+        upstream_totals: Default::default(),
+        production: meter
+            .f64_gauge("solar.production")
+            .with_description("Current production reported by the inverter")
+            .with_unit("W")
+            .build(),
+        energy_today: meter
+            .f64_gauge("solar.energy.today")
+            .with_description("Energy generated so far today")
+            .with_unit("kWh")
+            .build(),
+        upstream_requests: meter
+            .u64_counter("solar.upstream.requests")
+            .with_description("Upstream calls made while polling, by outcome")
+            .build(),
+        upstream_duration: meter
+            .f64_histogram("solar.upstream.duration")
+            .with_description("Latency of upstream calls made while polling")
+            .with_unit("s")
+            .build(),
+        sems_logins: meter
+            .u64_counter("solar.sems.logins")
+            .with_description("Fresh SEMS logins, excluding cached tokens")
+            .build(),
+        db_query_duration: meter
+            .f64_histogram("db.client.operation.duration")
+            .with_description("Latency of database queries")
+            .with_unit("s")
+            .build(),
+        http_requests: meter
+            .u64_counter("http.server.requests")
+            .with_description("HTTP requests handled, by route and status")
+            .build(),
+    }
+});
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    pub fn record_reading(&self, production_w: f64, energy_today_kwh: f64) {
+        self.production.record(production_w, &[]);
+        self.energy_today.record(energy_today_kwh, &[]);
+    }
+
+    pub fn record_upstream<T, E>(
+        &self,
+        upstream: Upstream,
+        elapsed: Duration,
+        result: &Result<T, E>,
+    ) {
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        self.upstream_totals[upstream as usize][usize::from(result.is_err())]
+            .fetch_add(1, Ordering::Relaxed);
+
+        self.upstream_requests.add(
+            1,
+            &[
+                KeyValue::new("upstream", upstream.as_str()),
+                KeyValue::new("outcome", outcome),
+            ],
+        );
+        self.upstream_duration.record(
+            elapsed.as_secs_f64(),
+            &[KeyValue::new("upstream", upstream.as_str())],
+        );
+    }
+
+    pub fn record_sems_login(&self) {
+        self.sems_logins.add(1, &[]);
+    }
+
+    /// Returns `(successes, failures)` for an upstream since startup.
+    pub fn upstream_totals(&self, upstream: Upstream) -> (u64, u64) {
+        let [successes, failures] = &self.upstream_totals[upstream as usize];
+
+        (
+            successes.load(Ordering::Relaxed),
+            failures.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Records how long a query future takes under the given name, alongside the
+/// tracing span it is usually instrumented with.
+pub trait Timed: Future + Sized {
+    async fn timed(self, query: &'static str) -> Self::Output {
+        let started = Instant::now();
+        let output = self.await;
+
+        metrics().db_query_duration.record(
+            started.elapsed().as_secs_f64(),
+            &[KeyValue::new("db.query.summary", query)],
+        );
+
+        output
+    }
+}
+
+impl<F: Future> Timed for F {}
+
+/// Axum middleware counting requests per matched route.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+
+    let response = next.run(request).await;
+
+    metrics().http_requests.add(
+        1,
+        &[
+            KeyValue::new("http.route", route),
+            KeyValue::new("http.request.method", method),
+            KeyValue::new(
+                "http.response.status_code",
+                i64::from(response.status().as_u16()),
+            ),
+        ],
+    );
+
+    response
+}
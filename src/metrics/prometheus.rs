@@ -0,0 +1,118 @@
+use std::fmt::Write;
+
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use sqlx::{PgPool, prelude::FromRow};
+
+use super::{Timed, Upstream, metrics};
+
+/// The `raw_data` fields read from the latest stored SEMS response.
+#[derive(Deserialize)]
+struct LatestKpi {
+    pac: f64,
+    power: f64,
+    total_power: f64,
+}
+
+#[derive(FromRow)]
+struct LatestReading {
+    time: NaiveDateTime,
+    kpi: sqlx::types::Json<LatestKpi>,
+    uv_level: Option<f64>,
+    temperature: Option<f64>,
+}
+
+/// Renders the current state in the Prometheus text exposition format.
+pub async fn render(db: &PgPool) -> Result<String, sqlx::Error> {
+    // backfilled rows carry no SEMS totals, so only look at polled readings
+    let latest: Option<LatestReading> = sqlx::query_as(
+        r#"SELECT time, raw_data->'data'->'kpi' AS kpi, uv_level, temperature
+           FROM solar_data_tsdb
+           WHERE NOT backfilled
+           ORDER BY time DESC
+           LIMIT 1"#,
+    )
+    .fetch_optional(db)
+    .timed("prometheus_latest")
+    .await?;
+
+    let token_age: Option<f64> = sqlx::query_scalar(
+        "SELECT EXTRACT(EPOCH FROM now() - max(created_at))::float8 FROM cached_token",
+    )
+    .fetch_one(db)
+    .timed("prometheus_token_age")
+    .await?;
+
+    let mut out = String::new();
+
+    if let Some(latest) = &latest {
+        gauge(
+            &mut out,
+            "solar_production_watts",
+            "Current production reported by the inverter.",
+            latest.kpi.pac,
+        );
+        gauge(
+            &mut out,
+            "solar_energy_today_kwh",
+            "Energy generated so far today.",
+            latest.kpi.power,
+        );
+        gauge(
+            &mut out,
+            "solar_energy_lifetime_kwh",
+            "Energy generated since the system was installed.",
+            latest.kpi.total_power,
+        );
+        if let Some(uv_level) = latest.uv_level {
+            gauge(&mut out, "solar_uv_index", "Latest UV index.", uv_level);
+        }
+        if let Some(temperature) = latest.temperature {
+            gauge(
+                &mut out,
+                "solar_temperature_celsius",
+                "Latest observed air temperature.",
+                temperature,
+            );
+        }
+        gauge(
+            &mut out,
+            "solar_last_poll_timestamp_seconds",
+            "Unix time of the last successful poll.",
+            latest.time.and_utc().timestamp() as f64,
+        );
+    }
+
+    if let Some(token_age) = token_age {
+        gauge(
+            &mut out,
+            "solar_sems_token_age_seconds",
+            "Age of the cached SEMS login token.",
+            token_age,
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP solar_upstream_requests_total Upstream calls made while polling since startup."
+    );
+    let _ = writeln!(out, "# TYPE solar_upstream_requests_total counter");
+    for upstream in Upstream::ALL {
+        let (successes, failures) = metrics().upstream_totals(upstream);
+        for (outcome, value) in [("success", successes), ("failure", failures)] {
+            let _ = writeln!(
+                out,
+                "solar_upstream_requests_total{{upstream=\"{}\",outcome=\"{outcome}\"}} {value}",
+                upstream.as_str()
+            );
+        }
+    }
+
+    Ok(out)
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
@@ -0,0 +1,333 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use tokio::task::JoinHandle;
+use tracing::instrument;
+use types::{DiscoveryConfig, DiscoveryDevice, Reading, Sensor};
+
+use crate::{config::types::MqttConfig, leader::LeaderElection};
+
+pub mod types;
+
+const SENSORS: [Sensor; 5] = [
+    Sensor {
+        key: "power",
+        name: "Solar power",
+        unit: Some("W"),
+        device_class: Some("power"),
+        state_class: "measurement",
+    },
+    Sensor {
+        key: "energy_today",
+        name: "Solar energy today",
+        unit: Some("kWh"),
+        device_class: Some("energy"),
+        state_class: "total_increasing",
+    },
+    Sensor {
+        key: "energy_lifetime",
+        name: "Solar energy lifetime",
+        unit: Some("kWh"),
+        device_class: Some("energy"),
+        state_class: "total_increasing",
+    },
+    Sensor {
+        key: "uv_index",
+        name: "UV index",
+        unit: None,
+        device_class: None,
+        state_class: "measurement",
+    },
+    Sensor {
+        key: "temperature",
+        name: "Outdoor temperature",
+        unit: Some("°C"),
+        device_class: Some("temperature"),
+        state_class: "measurement",
+    },
+];
+
+/// Publishes readings to an MQTT broker, announcing the sensors to Home
+/// Assistant through discovery each time the connection is established.
+///
+/// The connection is opened by the first reading, so replicas that aren't
+/// polling never connect. Only the lease holder sets a last will and reports
+/// availability, otherwise a standby going away would mark the sensors
+/// unavailable while the leader is still publishing.
+#[derive(Clone)]
+pub struct MqttPublisher {
+    options: MqttOptions,
+    topic_prefix: String,
+    discovery: Arc<[(String, String)]>,
+    leader: LeaderElection,
+    connection: Arc<Mutex<Option<Connection>>>,
+}
+
+struct Connection {
+    client: AsyncClient,
+    event_loop: JoinHandle<()>,
+    /// Whether this connection was opened by the lease holder.
+    announces: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MqttError {
+    /// The broker has been unreachable long enough for the request queue to
+    /// fill up, so the reading was dropped rather than stalling ingest.
+    #[error("the mqtt request queue is full, dropping the reading")]
+    QueueFull,
+}
+
+impl MqttPublisher {
+    /// `device_id` identifies this installation in Home Assistant, so the
+    /// power station id is used.
+    pub fn new(config: &MqttConfig, host: &str, device_id: &str, leader: LeaderElection) -> Self {
+        // brokers drop an existing session when another one connects with the
+        // same id, so the CLI and any other replicas need their own
+        let host_name = std::env::var("HOSTNAME").unwrap_or_else(|_| "solar".to_string());
+        let client_id = format!("{}-{host_name}-{}", config.client_id, std::process::id());
+
+        let mut options = MqttOptions::new(client_id, host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+        }
+
+        Self {
+            options,
+            topic_prefix: config.topic_prefix.clone(),
+            discovery: discovery_messages(config, device_id).into(),
+            leader,
+            connection: Arc::default(),
+        }
+    }
+
+    /// Queues the reading without waiting, so an unreachable broker can't
+    /// hold up ingest. The lease holder also marks the device online each
+    /// time, which corrects an `offline` left by a previous leader.
+    #[instrument(skip(self))]
+    pub fn publish(&self, reading: &Reading) -> Result<(), MqttError> {
+        let mut connection = self.connection.lock().unwrap();
+        let connection = connection.get_or_insert_with(|| self.connect());
+
+        let availability = connection
+            .announces
+            .then(|| (availability_topic(&self.topic_prefix), "online".to_string()));
+        for (topic, payload) in state_messages(&self.topic_prefix, reading)
+            .into_iter()
+            .chain(availability)
+        {
+            connection
+                .client
+                .try_publish(topic, QoS::AtLeastOnce, true, payload)
+                .map_err(|_| MqttError::QueueFull)?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks the device offline if this replica still holds the lease, and
+    /// waits for queued messages to be sent.
+    pub async fn disconnect(&self) {
+        let Some(connection) = self.connection.lock().unwrap().take() else {
+            return;
+        };
+
+        if connection.announces
+            && self.leader.is_leader()
+            && let Err(e) = connection.client.try_publish(
+                availability_topic(&self.topic_prefix),
+                QoS::AtLeastOnce,
+                true,
+                "offline",
+            )
+        {
+            tracing::warn!("error publishing mqtt availability: {e}");
+        }
+
+        if let Err(e) = connection.client.try_disconnect() {
+            tracing::warn!("error disconnecting from mqtt broker: {e}");
+        }
+
+        let _ = tokio::time::timeout(Duration::from_secs(5), connection.event_loop).await;
+    }
+
+    fn connect(&self) -> Connection {
+        let announces = self.leader.is_leader();
+        let availability_topic = availability_topic(&self.topic_prefix);
+
+        let mut options = self.options.clone();
+        if announces {
+            options.set_last_will(LastWill::new(
+                &availability_topic,
+                "offline",
+                QoS::AtLeastOnce,
+                true,
+            ));
+        }
+
+        let (client, event_loop) = AsyncClient::new(options, 64);
+        let event_loop = tokio::spawn(run_event_loop(
+            client.clone(),
+            event_loop,
+            announces.then_some(availability_topic),
+            self.discovery.clone(),
+        ));
+
+        Connection {
+            client,
+            event_loop,
+            announces,
+        }
+    }
+}
+
+fn availability_topic(topic_prefix: &str) -> String {
+    format!("{topic_prefix}/status")
+}
+
+/// Retained state for each sensor that has a value.
+fn state_messages(topic_prefix: &str, reading: &Reading) -> Vec<(String, String)> {
+    let values = [
+        ("power", Some(reading.power_w)),
+        ("energy_today", Some(reading.energy_today_kwh)),
+        ("energy_lifetime", Some(reading.energy_lifetime_kwh)),
+        ("uv_index", reading.uv_level),
+        ("temperature", reading.temperature),
+    ];
+
+    values
+        .into_iter()
+        .filter_map(|(key, value)| Some((format!("{topic_prefix}/{key}"), value?.to_string())))
+        .collect()
+}
+
+fn discovery_messages(config: &MqttConfig, device_id: &str) -> Vec<(String, String)> {
+    SENSORS
+        .iter()
+        .map(|sensor| {
+            let unique_id = format!("solar_panels_{device_id}_{}", sensor.key);
+            let payload = DiscoveryConfig {
+                name: sensor.name,
+                unique_id: unique_id.clone(),
+                state_topic: format!("{}/{}", config.topic_prefix, sensor.key),
+                availability_topic: availability_topic(&config.topic_prefix),
+                unit_of_measurement: sensor.unit,
+                device_class: sensor.device_class,
+                state_class: sensor.state_class,
+                device: DiscoveryDevice {
+                    identifiers: [format!("solar_panels_{device_id}")],
+                    name: "Solar panels",
+                    manufacturer: "GoodWe",
+                },
+            };
+
+            (
+                format!("{}/sensor/{unique_id}/config", config.discovery_prefix),
+                serde_json::to_string(&payload).expect("discovery config must serialise"),
+            )
+        })
+        .collect()
+}
+
+async fn run_event_loop(
+    client: AsyncClient,
+    mut event_loop: EventLoop,
+    availability_topic: Option<String>,
+    discovery: Arc<[(String, String)]>,
+) {
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::info!("connected to mqtt broker");
+
+                // the event loop isn't polled while this runs, so don't wait
+                // on the request channel
+                let messages = discovery
+                    .iter()
+                    .map(|(topic, payload)| (topic.as_str(), payload.as_str()))
+                    .chain(availability_topic.as_deref().map(|topic| (topic, "online")));
+                for (topic, payload) in messages {
+                    if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+                        tracing::warn!("error publishing mqtt discovery: {e}");
+                    }
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("mqtt connection error: {e}");
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_messages_skip_missing_values() {
+        let reading = Reading {
+            power_w: 1250.5,
+            energy_today_kwh: 12.3,
+            energy_lifetime_kwh: 15000.0,
+            uv_level: None,
+            temperature: Some(21.5),
+        };
+
+        assert_eq!(
+            state_messages("solar", &reading),
+            [
+                ("solar/power", "1250.5"),
+                ("solar/energy_today", "12.3"),
+                ("solar/energy_lifetime", "15000"),
+                ("solar/temperature", "21.5"),
+            ]
+            .map(|(topic, payload)| (topic.to_string(), payload.to_string()))
+        );
+    }
+
+    #[test]
+    fn discovery_messages_describe_each_sensor() {
+        let config = MqttConfig::default();
+        let messages = discovery_messages(&config, "station-1");
+
+        assert_eq!(messages.len(), SENSORS.len());
+
+        let (topic, payload) = &messages[0];
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/solar_panels_station-1_power/config"
+        );
+        let payload: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "name": "Solar power",
+                "unique_id": "solar_panels_station-1_power",
+                "state_topic": "solar/power",
+                "availability_topic": "solar/status",
+                "unit_of_measurement": "W",
+                "device_class": "power",
+                "state_class": "measurement",
+                "device": {
+                    "identifiers": ["solar_panels_station-1"],
+                    "name": "Solar panels",
+                    "manufacturer": "GoodWe",
+                },
+            })
+        );
+
+        // sensors without a unit or class leave the keys out
+        let (_, payload) = &messages[3];
+        let payload: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(payload["state_topic"], "solar/uv_index");
+        assert!(payload.get("unit_of_measurement").is_none());
+        assert!(payload.get("device_class").is_none());
+    }
+}
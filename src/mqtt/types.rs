@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+/// The values published after each successful poll.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reading {
+    pub power_w: f64,
+    pub energy_today_kwh: f64,
+    pub energy_lifetime_kwh: f64,
+    pub uv_level: Option<f64>,
+    pub temperature: Option<f64>,
+}
+
+pub struct Sensor {
+    pub key: &'static str,
+    pub name: &'static str,
+    pub unit: Option<&'static str>,
+    pub device_class: Option<&'static str>,
+    pub state_class: &'static str,
+}
+
+/// Home Assistant MQTT discovery payload for a single sensor.
+#[derive(Debug, Serialize)]
+pub struct DiscoveryConfig<'a> {
+    pub name: &'a str,
+    pub unique_id: String,
+    pub state_topic: String,
+    pub availability_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_of_measurement: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<&'a str>,
+    pub state_class: &'a str,
+    pub device: DiscoveryDevice<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiscoveryDevice<'a> {
+    pub identifiers: [String; 1],
+    pub name: &'a str,
+    pub manufacturer: &'a str,
+}
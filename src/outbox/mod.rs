@@ -0,0 +1,240 @@
+use std::time::{Duration, Instant};
+
+use reqwest_tracing::TracingMiddleware;
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+use types::{OutboxDepth, OutboxEntry, OutboxStatus};
+
+use crate::{
+    config::types::{HomeGatewayConfig, OutboxConfig},
+    leader::LeaderElection,
+    metrics::{Timed, Upstream, metrics},
+    tracing_setup::TimeTrace,
+    webhooks::{self, Webhooks},
+};
+
+pub mod types;
+
+/// Destination for the readings pushed to the home gateway.
+pub const HOME_GATEWAY: &str = "home_gateway";
+
+/// Delivers payloads written to the `outbox` table, retrying failures with
+/// exponential backoff.
+#[derive(Clone)]
+pub struct Outbox {
+    db: PgPool,
+    config: OutboxConfig,
+    home_gateway: HomeGatewayConfig,
+    webhooks: Webhooks,
+    http_client: reqwest_middleware::ClientWithMiddleware,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OutboxError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("a serialisation error occurred: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+impl Outbox {
+    const POLL_INTERVAL: Duration = Duration::from_secs(5);
+    const BATCH_SIZE: i64 = 50;
+    /// Long enough to send a full batch, each send timing out after 10s.
+    const CLAIM_LEASE: Duration = Duration::from_secs(10 * 60);
+
+    pub fn new(
+        db: PgPool,
+        config: OutboxConfig,
+        home_gateway: HomeGatewayConfig,
+        webhooks: Webhooks,
+    ) -> Self {
+        Self {
+            db,
+            config,
+            home_gateway,
+            webhooks,
+            http_client: reqwest_middleware::ClientBuilder::new(
+                reqwest::ClientBuilder::new()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .unwrap(),
+            )
+            .with(TracingMiddleware::<TimeTrace>::new())
+            .build(),
+        }
+    }
+
+    /// Queues a payload. Pass the transaction that stores the data it
+    /// describes so the two are committed together.
+    pub async fn enqueue(
+        conn: &mut PgConnection,
+        destination: &str,
+        payload: &impl Serialize,
+    ) -> Result<(), OutboxError> {
+        sqlx::query("INSERT INTO outbox (destination, payload) VALUES ($1, $2)")
+            .bind(destination)
+            .bind(serde_json::to_value(payload)?)
+            .execute(conn)
+            .timed("outbox_enqueue")
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn depth(&self) -> Result<OutboxDepth, OutboxError> {
+        let depth = sqlx::query_as(
+            r#"SELECT count(*) FILTER (WHERE status = 'pending') AS pending,
+                      count(*) FILTER (WHERE status = 'failed') AS failed
+               FROM outbox
+               WHERE status <> 'delivered'"#,
+        )
+        .fetch_one(&self.db)
+        .timed("outbox_depth")
+        .await?;
+
+        Ok(depth)
+    }
+
+    /// Delivers due entries until `shutdown` is cancelled.
+    pub async fn run(&self, leader: LeaderElection, shutdown: CancellationToken) {
+        loop {
+            if leader.is_leader() {
+                match self.deliver_due().await {
+                    Ok(0) => {}
+                    Ok(n) => tracing::info!("processed {n} outbox entries"),
+                    Err(e) => tracing::error!("error delivering outbox: {e}"),
+                }
+            }
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(Self::POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn deliver_due(&self) -> Result<usize, OutboxError> {
+        // claiming pushes next_attempt_at out by the lease, so another replica
+        // skips these rows while they're sent without holding a transaction
+        // open; if this one dies they become due again once the lease lapses
+        let mut entries: Vec<OutboxEntry> = sqlx::query_as(
+            r#"UPDATE outbox
+               SET next_attempt_at = now() + MAKE_INTERVAL(secs => $2)
+               WHERE id IN (
+                   SELECT id
+                   FROM outbox
+                   WHERE status = 'pending' AND next_attempt_at <= now()
+                   ORDER BY id
+                   LIMIT $1
+                   FOR UPDATE SKIP LOCKED
+               )
+               RETURNING id, destination, payload, attempts"#,
+        )
+        .bind(Self::BATCH_SIZE)
+        .bind(Self::CLAIM_LEASE.as_secs_f64())
+        .fetch_all(&self.db)
+        .timed("outbox_claim")
+        .await?;
+        entries.sort_by_key(|entry| entry.id);
+
+        for entry in &entries {
+            let attempts = entry.attempts + 1;
+
+            match self.send(entry).await {
+                Ok(()) => {
+                    sqlx::query(
+                        r#"UPDATE outbox
+                           SET status = $2, attempts = $3, last_error = NULL, delivered_at = now()
+                           WHERE id = $1"#,
+                    )
+                    .bind(entry.id)
+                    .bind(OutboxStatus::Delivered.as_str())
+                    .bind(attempts)
+                    .execute(&self.db)
+                    .await?;
+                }
+                Err(e) => {
+                    let status = if attempts >= self.config.max_attempts {
+                        tracing::error!(
+                            "giving up on outbox entry {} to {} after {attempts} attempts: {e}",
+                            entry.id,
+                            entry.destination
+                        );
+                        OutboxStatus::Failed
+                    } else {
+                        tracing::warn!(
+                            "outbox entry {} to {} failed, retrying: {e}",
+                            entry.id,
+                            entry.destination
+                        );
+                        OutboxStatus::Pending
+                    };
+
+                    sqlx::query(
+                        r#"UPDATE outbox
+                           SET status = $2, attempts = $3, last_error = $4,
+                               next_attempt_at = now() + MAKE_INTERVAL(secs => $5)
+                           WHERE id = $1"#,
+                    )
+                    .bind(entry.id)
+                    .bind(status.as_str())
+                    .bind(attempts)
+                    .bind(e)
+                    .bind(self.backoff(attempts).as_secs_f64())
+                    .execute(&self.db)
+                    .await?;
+                }
+            }
+        }
+
+        Ok(entries.len())
+    }
+
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = u32::try_from(attempts - 1).unwrap_or(0).min(16);
+        let secs = self
+            .config
+            .base_backoff_secs
+            .saturating_mul(1 << exponent)
+            .min(self.config.max_backoff_secs);
+
+        Duration::from_secs(secs)
+    }
+
+    async fn send(&self, entry: &OutboxEntry) -> Result<(), String> {
+        match entry.destination.as_str() {
+            HOME_GATEWAY => {
+                let Some(base_url) = &self.home_gateway.base_url else {
+                    return Err("home gateway is not configured".to_string());
+                };
+
+                let started = Instant::now();
+                let result = self
+                    .http_client
+                    .post(format!("{base_url}/v1/ingest/solar"))
+                    .header("X-Api-Key", &self.home_gateway.api_key)
+                    .json(&entry.payload)
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|response| {
+                        response
+                            .error_for_status()
+                            .map(|_| ())
+                            .map_err(|e| e.to_string())
+                    });
+                metrics().record_upstream(Upstream::HomeGateway, started.elapsed(), &result);
+
+                result
+            }
+            destination => match destination.strip_prefix(webhooks::DESTINATION_PREFIX) {
+                Some(name) => self.webhooks.deliver(name, entry).await,
+                None => Err(format!("unknown destination {destination}")),
+            },
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl OutboxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutboxStatus::Pending => "pending",
+            OutboxStatus::Delivered => "delivered",
+            OutboxStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub destination: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxDepth {
+    pub pending: i64,
+    pub failed: i64,
+}
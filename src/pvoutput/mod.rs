@@ -0,0 +1,345 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use reqwest::{StatusCode, header::HeaderMap};
+use reqwest_tracing::TracingMiddleware;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+use types::{Status, UploadState};
+
+use crate::{
+    config::types::PvOutputConfig,
+    leader::LeaderElection,
+    metrics::{Timed, Upstream, metrics},
+    summary::{SummaryError, SummaryService, types::DailySummary},
+    tracing_setup::TimeTrace,
+};
+
+pub mod types;
+
+/// Uploads statuses and end-of-day outputs to PVOutput, catching up on
+/// anything missed while the service or PVOutput was down.
+#[derive(Clone)]
+pub struct PvOutput {
+    db: PgPool,
+    config: PvOutputConfig,
+    api_key: String,
+    system_id: String,
+    summary: SummaryService,
+    http_client: reqwest_middleware::ClientWithMiddleware,
+    /// Set when the hourly request limit is used up.
+    paused_until: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PvOutputError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("a http error occurred: {0}")]
+    Http(#[from] reqwest_middleware::Error),
+    #[error("a summary error occurred: {0}")]
+    Summary(#[from] SummaryError),
+    #[error("rate limit reached, paused until {0}")]
+    RateLimited(DateTime<Utc>),
+    #[error("request rejected with {status}: {body}")]
+    Rejected { status: StatusCode, body: String },
+}
+
+impl PvOutput {
+    const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+    /// Returns `None` when no API key or system id is configured.
+    pub fn new(db: PgPool, config: PvOutputConfig, summary: SummaryService) -> Option<Self> {
+        let (api_key, system_id) = config.credentials()?;
+        let (api_key, system_id) = (api_key.to_owned(), system_id.to_owned());
+
+        Some(Self {
+            db,
+            config,
+            api_key,
+            system_id,
+            summary,
+            http_client: reqwest_middleware::ClientBuilder::new(
+                reqwest::ClientBuilder::new()
+                    .timeout(Duration::from_secs(30))
+                    .build()
+                    .unwrap(),
+            )
+            .with(TracingMiddleware::<TimeTrace>::new())
+            .build(),
+            paused_until: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Uploads new data every minute until `shutdown` is cancelled.
+    pub async fn run(&self, leader: LeaderElection, shutdown: CancellationToken) {
+        loop {
+            if leader.is_leader() {
+                match self.upload().await {
+                    Ok(()) => {}
+                    Err(e @ PvOutputError::RateLimited(_)) => tracing::info!("pvoutput: {e}"),
+                    Err(e) => tracing::error!("error uploading to pvoutput: {e}"),
+                }
+            }
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(Self::POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    #[instrument(name = "PvOutput::upload", skip(self), fields(otel.kind = "internal"))]
+    async fn upload(&self) -> Result<(), PvOutputError> {
+        let now = Utc::now()
+            .with_timezone(&chrono_tz::Australia::Perth)
+            .naive_local();
+        let state: UploadState =
+            sqlx::query_as("SELECT status_until, output_until FROM pvoutput_state")
+                .fetch_optional(&self.db)
+                .timed("pvoutput_state")
+                .await?
+                .unwrap_or_default();
+
+        self.upload_statuses(now, state.status_until).await?;
+        self.upload_outputs(now.date(), state.output_until).await?;
+
+        Ok(())
+    }
+
+    /// Sends each completed interval since the last upload, using a batch
+    /// request when more than one is waiting.
+    async fn upload_statuses(
+        &self,
+        now: NaiveDateTime,
+        until: Option<NaiveDateTime>,
+    ) -> Result<(), PvOutputError> {
+        let interval = chrono::Duration::minutes(i64::from(self.config.status_interval_mins));
+        let earliest = now - chrono::Duration::days(self.config.max_backfill_days);
+        let mut from = until
+            .unwrap_or_else(|| now.date().and_time(NaiveTime::MIN))
+            .max(Status::interval_start(
+                earliest,
+                self.config.status_interval_mins,
+            ));
+
+        loop {
+            let statuses: Vec<Status> = sqlx::query_as(
+                r#"SELECT DISTINCT ON (bucket) bucket AS local_time,
+                          current_kwh AS power_w,
+                          (raw_data->'data'->'kpi'->>'power')::float8 AS energy_today_kwh,
+                          temperature
+                   FROM (
+                       SELECT time_bucket(MAKE_INTERVAL(mins => $1), time + '8 hour') AS bucket, *
+                       FROM solar_data_tsdb
+                       WHERE (time + '8 hour') >= $2
+                   ) readings
+                   WHERE bucket + MAKE_INTERVAL(mins => $1) <= $3
+                   ORDER BY bucket, time DESC
+                   LIMIT $4"#,
+            )
+            .bind(self.config.status_interval_mins)
+            .bind(from)
+            .bind(now)
+            .bind(self.config.batch_size as i64)
+            .fetch_all(&self.db)
+            .timed("pvoutput_statuses")
+            .await?;
+
+            let Some(last) = statuses.last() else {
+                break;
+            };
+
+            self.send_statuses(&statuses).await?;
+            tracing::info!(
+                "uploaded {} pvoutput statuses up to {}",
+                statuses.len(),
+                last.local_time
+            );
+
+            from = last.local_time + interval;
+            sqlx::query(
+                r#"INSERT INTO pvoutput_state (status_until) VALUES ($1)
+                   ON CONFLICT (id) DO UPDATE SET status_until = $1, updated_at = now()"#,
+            )
+            .bind(from)
+            .execute(&self.db)
+            .timed("pvoutput_status_until")
+            .await?;
+
+            if statuses.len() < self.config.batch_size {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends statuses in one batch request. When PVOutput rejects the batch,
+    /// each status is sent on its own so one bad entry only drops itself.
+    async fn send_statuses(&self, statuses: &[Status]) -> Result<(), PvOutputError> {
+        let result = match statuses {
+            [status] => self.post("addstatus.jsp", &status.params()).await,
+            statuses => {
+                let data = statuses
+                    .iter()
+                    .map(Status::batch_entry)
+                    .collect::<Vec<_>>()
+                    .join(";");
+                self.post("addbatchstatus.jsp", &[("data", data)]).await
+            }
+        };
+
+        match result {
+            Ok(_) => Ok(()),
+            // retrying the same data won't help, so move past it
+            Err(PvOutputError::Rejected { status, body }) if status == StatusCode::BAD_REQUEST => {
+                match statuses {
+                    [status] => {
+                        tracing::warn!(
+                            "pvoutput rejected the status for {}: {body}",
+                            status.local_time
+                        );
+                    }
+                    [first, .., last] => {
+                        tracing::warn!(
+                            "pvoutput rejected statuses from {} to {}, sending them one at a time: {body}",
+                            first.local_time,
+                            last.local_time
+                        );
+                        for status in statuses {
+                            Box::pin(self.send_statuses(std::slice::from_ref(status))).await?;
+                        }
+                    }
+                    [] => {}
+                }
+
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends the totals for each finished day since the last upload.
+    async fn upload_outputs(
+        &self,
+        today: NaiveDate,
+        until: Option<NaiveDate>,
+    ) -> Result<(), PvOutputError> {
+        let earliest = today - chrono::Duration::days(self.config.max_backfill_days);
+        let mut date = until
+            .map(|date| date + chrono::Duration::days(1))
+            .unwrap_or(today - chrono::Duration::days(1))
+            .max(earliest);
+
+        while date < today {
+            if let Some(summary) = self.summary.compute(date, None).await? {
+                match self.post("addoutput.jsp", &output_params(&summary)).await {
+                    Ok(_) => tracing::info!("uploaded pvoutput output for {date}"),
+                    Err(PvOutputError::Rejected { status, body })
+                        if status == StatusCode::BAD_REQUEST =>
+                    {
+                        tracing::warn!("pvoutput rejected output for {date}: {body}");
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            sqlx::query(
+                r#"INSERT INTO pvoutput_state (output_until) VALUES ($1)
+                   ON CONFLICT (id) DO UPDATE SET output_until = $1, updated_at = now()"#,
+            )
+            .bind(date)
+            .execute(&self.db)
+            .timed("pvoutput_output_until")
+            .await?;
+
+            date += chrono::Duration::days(1);
+        }
+
+        Ok(())
+    }
+
+    async fn post(
+        &self,
+        service: &str,
+        params: &[(&'static str, String)],
+    ) -> Result<String, PvOutputError> {
+        if let Some(paused_until) = *self.paused_until.lock().unwrap()
+            && Utc::now() < paused_until
+        {
+            return Err(PvOutputError::RateLimited(paused_until));
+        }
+
+        let started = Instant::now();
+        let result = self.send(service, params).await;
+        metrics().record_upstream(Upstream::PvOutput, started.elapsed(), &result);
+
+        result
+    }
+
+    async fn send(
+        &self,
+        service: &str,
+        params: &[(&'static str, String)],
+    ) -> Result<String, PvOutputError> {
+        let response = self
+            .http_client
+            .post(format!("{}/service/r2/{service}", self.config.base_url))
+            .header("X-Pvoutput-Apikey", &self.api_key)
+            .header("X-Pvoutput-SystemId", &self.system_id)
+            .header("X-Rate-Limit", "1")
+            .form(params)
+            .send()
+            .await?;
+
+        let status = response.status();
+        let remaining: Option<u32> = header(response.headers(), "X-Rate-Limit-Remaining");
+        let reset = header(response.headers(), "X-Rate-Limit-Reset")
+            .and_then(|reset| DateTime::from_timestamp(reset, 0));
+        let body = response
+            .text()
+            .await
+            .map_err(reqwest_middleware::Error::from)?;
+
+        let exceeded = status == StatusCode::TOO_MANY_REQUESTS
+            || (status == StatusCode::FORBIDDEN && body.contains("Exceeded"));
+        if exceeded || remaining == Some(0) {
+            let paused_until = reset.unwrap_or_else(|| Utc::now() + chrono::Duration::hours(1));
+            *self.paused_until.lock().unwrap() = Some(paused_until);
+
+            if exceeded {
+                return Err(PvOutputError::RateLimited(paused_until));
+            }
+        }
+
+        if !status.is_success() {
+            return Err(PvOutputError::Rejected { status, body });
+        }
+
+        Ok(body)
+    }
+}
+
+fn header<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Parameters for `addoutput`. Peak times are reported in local time.
+fn output_params(summary: &DailySummary) -> Vec<(&'static str, String)> {
+    let peak_at = Utc
+        .from_utc_datetime(&summary.peak_at)
+        .with_timezone(&chrono_tz::Australia::Perth);
+
+    vec![
+        ("d", summary.date.format("%Y%m%d").to_string()),
+        ("g", format!("{:.0}", summary.total_kwh * 1000.0)),
+        ("pp", format!("{:.0}", summary.peak_w)),
+        ("pt", peak_at.format("%H:%M").to_string()),
+    ]
+}
@@ -0,0 +1,122 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use sqlx::prelude::FromRow;
+
+/// The last reading in a status interval, labelled with the interval's
+/// local start time.
+#[derive(Debug, Clone, FromRow)]
+pub struct Status {
+    pub local_time: NaiveDateTime,
+    pub power_w: f64,
+    /// Missing for backfilled readings, which carry no SEMS totals.
+    pub energy_today_kwh: Option<f64>,
+    pub temperature: Option<f64>,
+}
+
+impl Status {
+    /// The start of the `interval_mins` interval containing `time`, matching
+    /// `time_bucket` for intervals that divide a day.
+    pub fn interval_start(time: NaiveDateTime, interval_mins: i32) -> NaiveDateTime {
+        let interval_secs = i64::from(interval_mins) * 60;
+        let secs = time.and_utc().timestamp();
+
+        DateTime::from_timestamp(secs - secs.rem_euclid(interval_secs), 0)
+            .map_or(time, |start| start.naive_utc())
+    }
+
+    /// Parameters for `addstatus`.
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![
+            ("d", self.local_time.format("%Y%m%d").to_string()),
+            ("t", self.local_time.format("%H:%M").to_string()),
+            ("v2", format!("{:.0}", self.power_w)),
+        ];
+        if let Some(energy_wh) = self.energy_wh() {
+            params.push(("v1", energy_wh));
+        }
+        if let Some(temperature) = self.temperature {
+            params.push(("v5", format!("{temperature:.1}")));
+        }
+
+        params
+    }
+
+    /// A `d,t,v1,v2,v3,v4,v5` entry for `addbatchstatus`.
+    pub fn batch_entry(&self) -> String {
+        format!(
+            "{},{},{},{:.0},,,{}",
+            self.local_time.format("%Y%m%d"),
+            self.local_time.format("%H:%M"),
+            self.energy_wh().unwrap_or_default(),
+            self.power_w,
+            self.temperature
+                .map(|temperature| format!("{temperature:.1}"))
+                .unwrap_or_default()
+        )
+    }
+
+    fn energy_wh(&self) -> Option<String> {
+        self.energy_today_kwh
+            .map(|energy_kwh| format!("{:.0}", energy_kwh * 1000.0))
+    }
+}
+
+/// How far statuses and outputs have been uploaded.
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct UploadState {
+    /// Local start of the first interval not yet uploaded.
+    pub status_until: Option<NaiveDateTime>,
+    pub output_until: Option<NaiveDate>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn batch_entry_fills_every_field() {
+        let status = Status {
+            local_time: time("2026-10-19 09:05:00"),
+            power_w: 2345.6,
+            energy_today_kwh: Some(4.2504),
+            temperature: Some(31.25),
+        };
+
+        assert_eq!(status.batch_entry(), "20261019,09:05,4250,2346,,,31.2");
+    }
+
+    #[test]
+    fn batch_entry_leaves_missing_values_empty() {
+        let status = Status {
+            local_time: time("2026-10-19 16:50:00"),
+            power_w: 0.0,
+            energy_today_kwh: None,
+            temperature: None,
+        };
+
+        assert_eq!(status.batch_entry(), "20261019,16:50,,0,,,");
+    }
+
+    #[test]
+    fn interval_start_rounds_down_to_the_interval() {
+        assert_eq!(
+            Status::interval_start(time("2026-10-19 09:07:42"), 5),
+            time("2026-10-19 09:05:00")
+        );
+        assert_eq!(
+            Status::interval_start(time("2026-10-19 09:07:42"), 10),
+            time("2026-10-19 09:00:00")
+        );
+        assert_eq!(
+            Status::interval_start(time("2026-10-19 09:10:00"), 10),
+            time("2026-10-19 09:10:00")
+        );
+        assert_eq!(
+            Status::interval_start(time("2026-10-19 00:04:59"), 5),
+            time("2026-10-19 00:00:00")
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeSet;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use sqlx::PgPool;
+use tracing::instrument;
+use types::{
+    BackfilledDate, CounterRegression, DataQualityReport, Gap, OutOfRange, QualityReading, StaleRun,
+};
+
+use crate::{
+    background::schedule::PollSchedule,
+    config::types::QualityConfig,
+    goodwe::{GoodWeSemsAPI, GoodWeSemsAPIError},
+    metrics::Timed,
+};
+
+pub mod types;
+
+/// Scans stored readings for gaps and implausible values.
+#[derive(Clone)]
+pub struct DataQuality {
+    config: QualityConfig,
+    solar_api: GoodWeSemsAPI,
+    schedule: PollSchedule,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum QualityError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("a solar api error occurred: {0}")]
+    SolarAPI(#[from] GoodWeSemsAPIError),
+    #[error("invalid range: {0}")]
+    InvalidRange(&'static str),
+}
+
+impl DataQuality {
+    /// Longest range a single scan may cover.
+    const MAX_RANGE_DAYS: i64 = 31;
+    /// Each list in a report is cut off after this many entries.
+    const MAX_ITEMS: usize = 500;
+    const MAX_UV_LEVEL: f64 = 20.0;
+    const TEMPERATURE_RANGE: std::ops::RangeInclusive<f64> = -20.0..=55.0;
+
+    pub fn new(config: QualityConfig, solar_api: GoodWeSemsAPI, schedule: PollSchedule) -> Self {
+        Self {
+            config,
+            solar_api,
+            schedule,
+        }
+    }
+
+    /// Whether `/api/data-quality` may trigger a backfill.
+    pub fn api_backfill(&self) -> bool {
+        self.config.api_backfill
+    }
+
+    fn db(&self) -> &PgPool {
+        self.solar_api.db()
+    }
+
+    /// Reports on the readings in `[from, to)`, given in UTC. With `backfill`,
+    /// past days containing gaps are first refilled from the SEMS power chart.
+    #[instrument(skip(self))]
+    pub async fn scan(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        backfill: bool,
+    ) -> Result<DataQualityReport, QualityError> {
+        if from >= to {
+            return Err(QualityError::InvalidRange("from must be before to"));
+        }
+        if to - from > Duration::days(Self::MAX_RANGE_DAYS) {
+            return Err(QualityError::InvalidRange("ranges are limited to 31 days"));
+        }
+
+        let mut report = self.analyse(from, to).await?;
+        if !backfill {
+            return Ok(report);
+        }
+
+        let backfilled = self.backfill(&report.gaps).await?;
+        if !backfilled.is_empty() {
+            report = self.analyse(from, to).await?;
+        }
+        report.backfilled = backfilled;
+
+        Ok(report)
+    }
+
+    async fn analyse(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<DataQualityReport, QualityError> {
+        let readings: Vec<QualityReading> = sqlx::query_as(
+            r#"SELECT time, current_kwh, uv_level, temperature,
+                      (raw_data->'data'->'kpi'->>'total_power')::float8 AS energy_lifetime_kwh
+               FROM solar_data_tsdb
+               WHERE time >= $1 AND time < $2
+               ORDER BY time"#,
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(self.db())
+        .timed("quality_readings")
+        .await?;
+
+        let mut report = DataQualityReport {
+            from,
+            to,
+            samples: readings.len(),
+            gaps: self.gaps(&readings, from, to),
+            stale_runs: self.stale_runs(&readings),
+            out_of_range: self.out_of_range(&readings),
+            counter_regressions: counter_regressions(&readings),
+            ..Default::default()
+        };
+        report.missing_minutes = report.gaps.iter().map(|gap| gap.minutes).sum();
+
+        Ok(report)
+    }
+
+    fn gaps(
+        &self,
+        readings: &[QualityReading],
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Vec<Gap> {
+        let max_gap = Duration::seconds(self.config.max_gap_secs);
+        let times = std::iter::once(from)
+            .chain(readings.iter().map(|reading| reading.time))
+            .chain(std::iter::once(to));
+
+        times
+            .clone()
+            .zip(times.skip(1))
+            // overnight polls are further apart by design
+            .filter(|(start, end)| {
+                *end - *start > max_gap + self.schedule.interval(start.and_utc())
+            })
+            .map(|(start, end)| Gap {
+                start,
+                end,
+                minutes: (end - start).num_minutes(),
+            })
+            .take(Self::MAX_ITEMS)
+            .collect()
+    }
+
+    fn stale_runs(&self, readings: &[QualityReading]) -> Vec<StaleRun> {
+        let min_length = Duration::minutes(self.config.stale_mins);
+        let mut runs = Vec::new();
+
+        // zero is expected overnight, so only repeated output counts
+        for run in readings.chunk_by(|a, b| a.current_kwh == b.current_kwh) {
+            let (first, last) = (&run[0], &run[run.len() - 1]);
+            if first.current_kwh > 0.0 && last.time - first.time >= min_length {
+                runs.push(StaleRun {
+                    start: first.time,
+                    end: last.time,
+                    samples: run.len(),
+                    value: first.current_kwh,
+                });
+            }
+        }
+
+        runs.truncate(Self::MAX_ITEMS);
+        runs
+    }
+
+    fn out_of_range(&self, readings: &[QualityReading]) -> Vec<OutOfRange> {
+        let mut found = Vec::new();
+
+        for reading in readings {
+            let mut flag = |field, value, reason| {
+                found.push(OutOfRange {
+                    time: reading.time,
+                    field,
+                    value,
+                    reason,
+                })
+            };
+
+            if reading.current_kwh < 0.0 {
+                flag("current_kwh", reading.current_kwh, "negative");
+            }
+            if let Some(capacity) = self.config.inverter_capacity_w
+                && reading.current_kwh > capacity
+            {
+                flag(
+                    "current_kwh",
+                    reading.current_kwh,
+                    "above inverter capacity",
+                );
+            }
+            if let Some(uv_level) = reading.uv_level
+                && !(0.0..=Self::MAX_UV_LEVEL).contains(&uv_level)
+            {
+                flag("uv_level", uv_level, "implausible UV index");
+            }
+            if let Some(temperature) = reading.temperature
+                && !Self::TEMPERATURE_RANGE.contains(&temperature)
+            {
+                flag("temperature", temperature, "implausible temperature");
+            }
+            if let Some(total_power) = reading.energy_lifetime_kwh
+                && total_power < 0.0
+            {
+                flag("total_power", total_power, "negative");
+            }
+        }
+
+        found.truncate(Self::MAX_ITEMS);
+        found
+    }
+
+    /// Refills each past local day touched by a gap, returning what was inserted.
+    async fn backfill(&self, gaps: &[Gap]) -> Result<Vec<BackfilledDate>, QualityError> {
+        let today = Utc::now()
+            .with_timezone(&chrono_tz::Australia::Perth)
+            .date_naive();
+        let local_date = |time: NaiveDateTime| {
+            Utc.from_utc_datetime(&time)
+                .with_timezone(&chrono_tz::Australia::Perth)
+                .date_naive()
+        };
+
+        let dates: BTreeSet<NaiveDate> = gaps
+            .iter()
+            .flat_map(|gap| {
+                local_date(gap.start)
+                    .iter_days()
+                    .take_while(move |date| *date <= local_date(gap.end))
+            })
+            .filter(|date| *date < today)
+            .collect();
+
+        if dates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let login = self.solar_api.get_new_or_cached_login_data().await?;
+        let mut backfilled = Vec::new();
+        for date in dates {
+            let inserted = self.solar_api.backfill_date(login.clone(), date).await?;
+            tracing::info!("backfilled {inserted} readings for {date}");
+            backfilled.push(BackfilledDate { date, inserted });
+        }
+
+        Ok(backfilled)
+    }
+}
+
+fn counter_regressions(readings: &[QualityReading]) -> Vec<CounterRegression> {
+    let totals = readings
+        .iter()
+        .filter_map(|reading| Some((reading.time, reading.energy_lifetime_kwh?)));
+
+    totals
+        .clone()
+        .zip(totals.skip(1))
+        .filter(|((_, previous), (_, current))| current < previous)
+        .map(
+            |((_, previous_kwh), (time, current_kwh))| CounterRegression {
+                time,
+                previous_kwh,
+                current_kwh,
+            },
+        )
+        .take(DataQuality::MAX_ITEMS)
+        .collect()
+}
@@ -0,0 +1,73 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct QualityReading {
+    pub time: NaiveDateTime,
+    pub current_kwh: f64,
+    pub uv_level: Option<f64>,
+    pub temperature: Option<f64>,
+    /// Missing for backfilled readings, which carry no SEMS totals.
+    pub energy_lifetime_kwh: Option<f64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataQualityReport {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub samples: usize,
+    pub missing_minutes: i64,
+    pub gaps: Vec<Gap>,
+    pub stale_runs: Vec<StaleRun>,
+    pub out_of_range: Vec<OutOfRange>,
+    pub counter_regressions: Vec<CounterRegression>,
+    /// Local dates fetched from the SEMS power chart before this report was made.
+    pub backfilled: Vec<BackfilledDate>,
+}
+
+/// A stretch without readings. `start` and `end` are the readings either
+/// side, or the bounds of the scanned range.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Gap {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub minutes: i64,
+}
+
+/// Consecutive non-zero readings with exactly the same production.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StaleRun {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub samples: usize,
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutOfRange {
+    pub time: NaiveDateTime,
+    pub field: &'static str,
+    pub value: f64,
+    pub reason: &'static str,
+}
+
+/// A lifetime energy total lower than the one before it.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CounterRegression {
+    pub time: NaiveDateTime,
+    pub previous_kwh: f64,
+    pub current_kwh: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackfilledDate {
+    pub date: NaiveDate,
+    pub inserted: u64,
+}
@@ -0,0 +1,77 @@
+use sqlx::PgPool;
+use tracing::instrument;
+use types::SweepCounts;
+
+use crate::{config::types::RetentionConfig, metrics::Timed, outbox::types::OutboxStatus};
+
+pub mod types;
+
+/// Deletes delivered outbox entries and old delivery and job logs, which
+/// would otherwise grow without bound.
+#[derive(Clone)]
+pub struct Retention {
+    db: PgPool,
+    config: RetentionConfig,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RetentionError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl Retention {
+    pub fn new(db: PgPool, config: RetentionConfig) -> Self {
+        Self { db, config }
+    }
+
+    #[instrument(name = "Retention::run_task", skip(self), fields(otel.kind = "internal"))]
+    pub async fn run_task(&self) {
+        match self.sweep().await {
+            Ok(counts) if counts.total() == 0 => {}
+            Ok(counts) => tracing::info!(
+                "deleted {} outbox entries, {} webhook deliveries and {} job runs older than {} days",
+                counts.outbox,
+                counts.webhook_deliveries,
+                counts.job_runs,
+                self.config.days
+            ),
+            Err(e) => tracing::error!("error deleting old rows: {e}"),
+        }
+    }
+
+    async fn sweep(&self) -> Result<SweepCounts, RetentionError> {
+        // failed entries are kept for inspection and still count in health
+        let outbox = sqlx::query(
+            r#"DELETE FROM outbox
+               WHERE status = $1 AND delivered_at < now() - MAKE_INTERVAL(days => $2)"#,
+        )
+        .bind(OutboxStatus::Delivered.as_str())
+        .bind(self.config.days)
+        .execute(&self.db)
+        .timed("retention_outbox")
+        .await?;
+
+        let webhook_deliveries = sqlx::query(
+            "DELETE FROM webhook_deliveries WHERE attempted_at < now() - MAKE_INTERVAL(days => $1)",
+        )
+        .bind(self.config.days)
+        .execute(&self.db)
+        .timed("retention_webhook_deliveries")
+        .await?;
+
+        let job_runs = sqlx::query(
+            "DELETE FROM job_runs WHERE started_at < now() - MAKE_INTERVAL(days => $1)",
+        )
+        .bind(self.config.days)
+        .execute(&self.db)
+        .timed("retention_job_runs")
+        .await?;
+
+        Ok(SweepCounts {
+            outbox: outbox.rows_affected(),
+            webhook_deliveries: webhook_deliveries.rows_affected(),
+            job_runs: job_runs.rows_affected(),
+        })
+    }
+}
@@ -0,0 +1,13 @@
+/// Rows deleted by a retention sweep.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SweepCounts {
+    pub outbox: u64,
+    pub webhook_deliveries: u64,
+    pub job_runs: u64,
+}
+
+impl SweepCounts {
+    pub fn total(&self) -> u64 {
+        self.outbox + self.webhook_deliveries + self.job_runs
+    }
+}
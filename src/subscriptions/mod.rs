@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use sqlx::{PgPool, prelude::FromRow};
+use tracing::instrument;
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    channel::message::Embed,
+    id::{Id, marker::UserMarker},
+};
+use types::SubscriptionKind;
+
+use crate::metrics::Timed;
+
+pub mod types;
+
+#[derive(Clone)]
+pub struct Subscriptions {
+    db: PgPool,
+    /// `None` when the Discord integration is disabled.
+    http: Option<Arc<HttpClient>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SubscriptionError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("a discord error occurred: {0}")]
+    Discord(#[from] twilight_http::Error),
+    #[error("a discord error occurred: {0}")]
+    DiscordBody(#[from] twilight_http::response::DeserializeBodyError),
+}
+
+impl Subscriptions {
+    pub fn new(db: PgPool, http: Option<Arc<HttpClient>>) -> Self {
+        Self { db, http }
+    }
+
+    /// Returns `false` if the user was already subscribed.
+    #[instrument(skip(self))]
+    pub async fn subscribe(
+        &self,
+        user_id: Id<UserMarker>,
+        kind: SubscriptionKind,
+    ) -> Result<bool, SubscriptionError> {
+        let result = sqlx::query(
+            "INSERT INTO subscriptions (user_id, kind) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(user_id.get() as i64)
+        .bind(kind.as_str())
+        .execute(&self.db)
+        .timed("subscription_insert")
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns `false` if the user wasn't subscribed.
+    #[instrument(skip(self))]
+    pub async fn unsubscribe(
+        &self,
+        user_id: Id<UserMarker>,
+        kind: SubscriptionKind,
+    ) -> Result<bool, SubscriptionError> {
+        let result = sqlx::query("DELETE FROM subscriptions WHERE user_id = $1 AND kind = $2")
+            .bind(user_id.get() as i64)
+            .bind(kind.as_str())
+            .execute(&self.db)
+            .timed("subscription_delete")
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn subscribers(
+        &self,
+        kind: SubscriptionKind,
+    ) -> Result<Vec<Id<UserMarker>>, SubscriptionError> {
+        #[derive(FromRow)]
+        struct Row {
+            user_id: i64,
+        }
+
+        let rows: Vec<Row> = sqlx::query_as("SELECT user_id FROM subscriptions WHERE kind = $1")
+            .bind(kind.as_str())
+            .fetch_all(&self.db)
+            .timed("subscribers")
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|r| Id::new_checked(r.user_id as u64))
+            .collect())
+    }
+
+    /// Sends the embed as a DM to everyone subscribed to `kind`.
+    #[instrument(skip(self, embed))]
+    pub async fn notify(
+        &self,
+        kind: SubscriptionKind,
+        embed: &Embed,
+    ) -> Result<(), SubscriptionError> {
+        let Some(http) = &self.http else {
+            return Ok(());
+        };
+
+        for user_id in self.subscribers(kind).await? {
+            if let Err(e) = Self::send_dm(http, user_id, embed).await {
+                tracing::error!("error sending {} to user {user_id}: {e}", kind.as_str());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_dm(
+        http: &HttpClient,
+        user_id: Id<UserMarker>,
+        embed: &Embed,
+    ) -> Result<(), SubscriptionError> {
+        let channel = http.create_private_channel(user_id).await?.model().await?;
+
+        http.create_message(channel.id)
+            .embeds(std::slice::from_ref(embed))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionKind {
+    DailySummary,
+    OutageAlerts,
+    RecordDays,
+    Forecast,
+}
+
+impl SubscriptionKind {
+    pub const ALL: [SubscriptionKind; 4] = [
+        SubscriptionKind::DailySummary,
+        SubscriptionKind::OutageAlerts,
+        SubscriptionKind::RecordDays,
+        SubscriptionKind::Forecast,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionKind::DailySummary => "daily_summary",
+            SubscriptionKind::OutageAlerts => "outage_alerts",
+            SubscriptionKind::RecordDays => "record_days",
+            SubscriptionKind::Forecast => "forecast",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            SubscriptionKind::DailySummary => "Daily summary",
+            SubscriptionKind::OutageAlerts => "Outage alerts",
+            SubscriptionKind::RecordDays => "Record days",
+            SubscriptionKind::Forecast => "Forecast",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == value)
+    }
+}
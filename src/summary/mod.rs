@@ -0,0 +1,565 @@
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDate};
+use sqlx::{PgPool, prelude::FromRow};
+use tracing::{Instrument, instrument};
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    channel::message::Embed,
+    id::{Id, marker::ChannelMarker},
+};
+use twilight_util::builder::embed::{EmbedBuilder, EmbedFieldBuilder};
+use types::{DailySummary, SummaryReport};
+
+use crate::{
+    metrics::Timed,
+    subscriptions::{Subscriptions, types::SubscriptionKind},
+    sun,
+    weather::{WeatherAPI, types::DailyForecast},
+    webhooks::{Webhooks, types::WebhookEvent},
+};
+
+pub mod types;
+
+#[derive(Clone)]
+pub struct SummaryService {
+    db: PgPool,
+    /// `None` when the Discord integration is disabled.
+    http: Option<Arc<HttpClient>>,
+    channel_id: Option<Id<ChannelMarker>>,
+    /// Station that readings without a `station_id` belong to.
+    powerstation_id: String,
+    subscriptions: Subscriptions,
+    weather_api: WeatherAPI,
+    webhooks: Webhooks,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SummaryError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("a discord error occurred: {0}")]
+    Discord(#[from] twilight_http::Error),
+    #[error("a subscription error occurred: {0}")]
+    Subscription(#[from] crate::subscriptions::SubscriptionError),
+    #[error("a weather error occurred: {0}")]
+    WeatherAPI(#[from] crate::weather::WeatherAPIError),
+    #[error("unknown error occurred: {0}")]
+    Unknown(#[from] anyhow::Error),
+}
+
+impl SummaryService {
+    /// How long after sunset to wait before posting, so the last readings are in.
+    const POST_DELAY_MINS: i64 = 30;
+
+    pub fn new(
+        db: PgPool,
+        http: Option<Arc<HttpClient>>,
+        channel_id: Option<Id<ChannelMarker>>,
+        powerstation_id: String,
+        subscriptions: Subscriptions,
+        weather_api: WeatherAPI,
+        webhooks: Webhooks,
+    ) -> Self {
+        Self {
+            db,
+            http,
+            channel_id,
+            powerstation_id,
+            subscriptions,
+            weather_api,
+            webhooks,
+        }
+    }
+
+    /// Computes the summary for a local date from its readings, across every
+    /// station when `station` is `None`.
+    #[instrument(skip(self))]
+    pub async fn compute(
+        &self,
+        date: NaiveDate,
+        station: Option<&str>,
+    ) -> Result<Option<DailySummary>, SummaryError> {
+        let summary: Option<DailySummary> = sqlx::query_as(
+            r#"WITH day AS (
+                   SELECT * FROM solar_data_tsdb
+                   WHERE (time + '8 hour')::date = $1
+                     AND ($2::text IS NULL OR coalesce(station_id, $3) = $2)
+               ),
+               latest AS (
+                   SELECT raw_data FROM day ORDER BY time DESC LIMIT 1
+               ),
+               peak AS (
+                   SELECT current_kwh, time FROM day ORDER BY current_kwh DESC, time ASC LIMIT 1
+               ),
+               -- backfilled days carry no SEMS totals, so integrate the readings instead
+               integrated AS (
+                   SELECT sum(current_kwh * extract(epoch FROM time - prev) / 3600) / 1000 AS kwh
+                   FROM (SELECT current_kwh, time, lag(time) OVER (ORDER BY time) AS prev FROM day) readings
+               )
+               SELECT $1::date AS date,
+                      COALESCE((latest.raw_data->'data'->'kpi'->>'power')::float8, integrated.kwh, 0) AS total_kwh,
+                      peak.current_kwh AS peak_w,
+                      peak.time AS peak_at,
+                      (SELECT avg(uv_level) FROM day) AS avg_uv_level,
+                      (SELECT avg(temperature) FROM day) AS avg_temperature,
+                      (latest.raw_data->'data'->'kpi'->>'day_income')::float8 AS income,
+                      latest.raw_data->'data'->'kpi'->>'currency' AS currency
+               FROM latest, peak, integrated"#,
+        )
+        .bind(date)
+        .bind(station)
+        .bind(&self.powerstation_id)
+        .fetch_optional(&self.db)
+        .instrument(tracing::info_span!("summary_for_date"))
+        .timed("summary_for_date")
+        .await?;
+
+        Ok(summary)
+    }
+
+    /// Computes the summary for a finished local date and stores it as that
+    /// day's rollup.
+    #[instrument(skip(self))]
+    pub async fn rollup(&self, date: NaiveDate) -> Result<Option<DailySummary>, SummaryError> {
+        let Some(summary) = self.compute(date, None).await? else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            r#"INSERT INTO daily_summaries (date, total_kwh, peak_w, peak_at, avg_uv_level, avg_temperature, income, currency)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+               ON CONFLICT (date) DO UPDATE
+               SET total_kwh = $2, peak_w = $3, peak_at = $4, avg_uv_level = $5,
+                   avg_temperature = $6, income = $7, currency = $8, updated_at = now()"#,
+        )
+        .bind(summary.date)
+        .bind(summary.total_kwh)
+        .bind(summary.peak_w)
+        .bind(summary.peak_at)
+        .bind(summary.avg_uv_level)
+        .bind(summary.avg_temperature)
+        .bind(summary.income)
+        .bind(&summary.currency)
+        .execute(&self.db)
+        .timed("summary_rollup")
+        .await?;
+
+        Ok(Some(summary))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn report(&self, date: NaiveDate) -> Result<Option<SummaryReport>, SummaryError> {
+        match self.compute(date, None).await? {
+            Some(summary) => Ok(Some(self.report_for(summary).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn report_for(&self, summary: DailySummary) -> Result<SummaryReport, SummaryError> {
+        let date = summary.date;
+        let yesterday = self.compute(date - Duration::days(1), None).await?;
+
+        Ok(SummaryReport {
+            summary,
+            yesterday_kwh: yesterday.map(|y| y.total_kwh),
+            average_30_day_kwh: self.average_kwh(date).await?,
+        })
+    }
+
+    /// Average daily total over the 30 days before `date`.
+    async fn average_kwh(&self, date: NaiveDate) -> Result<Option<f64>, SummaryError> {
+        #[derive(FromRow)]
+        struct Row {
+            avg_kwh: Option<f64>,
+        }
+
+        let average: Row = sqlx::query_as(
+            r#"SELECT avg(day_kwh) AS avg_kwh
+               FROM (
+                   SELECT DISTINCT ON ((time + '8 hour')::date) (raw_data->'data'->'kpi'->>'power')::float8 AS day_kwh
+                   FROM solar_data_tsdb
+                   WHERE (time + '8 hour')::date >= $1 - 30 AND (time + '8 hour')::date < $1
+                     AND NOT backfilled
+                   ORDER BY (time + '8 hour')::date, time DESC
+               ) days"#,
+        )
+        .bind(date)
+        .fetch_one(&self.db)
+        .instrument(tracing::info_span!("summary_30_day_average"))
+        .timed("summary_30_day_average")
+        .await?;
+
+        Ok(average.avg_kwh)
+    }
+
+    /// Sends the forecast after sunrise and posts today's summary once the sun has set.
+    #[instrument(name = "SummaryService::run_task", skip(self), fields(otel.kind = "internal"))]
+    pub async fn run_task(&self) {
+        if let Err(e) = self.post_forecast_if_due().await {
+            tracing::error!("error sending forecast: {e}");
+        }
+
+        if let Err(e) = self.post_if_due().await {
+            tracing::error!("error posting daily summary: {e}");
+        }
+    }
+
+    async fn post_forecast_if_due(&self) -> Result<(), SummaryError> {
+        let now = chrono::offset::Utc::now();
+        let today = now.with_timezone(&chrono_tz::Australia::Perth).date_naive();
+
+        let Some(sun_times) = sun::sun_times(today, &sun::SITE) else {
+            return Ok(());
+        };
+
+        if now < sun_times.sunrise || now > sun_times.sunset {
+            return Ok(());
+        }
+
+        let claimed =
+            sqlx::query("INSERT INTO forecast_posts (date) VALUES ($1) ON CONFLICT DO NOTHING")
+                .bind(today)
+                .execute(&self.db)
+                .timed("summary_forecast_claim")
+                .await?;
+
+        if claimed.rows_affected() == 0 {
+            return Ok(());
+        }
+
+        if let Err(e) = self.post_forecast(today).await {
+            // let the next run try again
+            sqlx::query("DELETE FROM forecast_posts WHERE date = $1")
+                .bind(today)
+                .execute(&self.db)
+                .timed("summary_forecast_release")
+                .await?;
+
+            return Err(e);
+        }
+
+        tracing::info!("sent forecast for {today}");
+
+        Ok(())
+    }
+
+    async fn post_forecast(&self, today: NaiveDate) -> Result<(), SummaryError> {
+        let forecast = self
+            .weather_api
+            .get_daily_forecast(WeatherAPI::JANDAKOT_GEOCODE)
+            .await?;
+        let average_kwh = self.average_kwh(today).await?;
+
+        self.subscriptions
+            .notify(
+                SubscriptionKind::Forecast,
+                &forecast_embed(today, &forecast, average_kwh)?,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn post_if_due(&self) -> Result<(), SummaryError> {
+        #[derive(FromRow)]
+        struct Row {
+            posted: bool,
+        }
+
+        let now = chrono::offset::Utc::now();
+        let today = now.with_timezone(&chrono_tz::Australia::Perth).date_naive();
+
+        let Some(sun_times) = sun::sun_times(today, &sun::SITE) else {
+            return Ok(());
+        };
+
+        if now < sun_times.sunset + Duration::minutes(Self::POST_DELAY_MINS) {
+            return Ok(());
+        }
+
+        let posted: Option<Row> = sqlx::query_as(
+            "SELECT posted_at IS NOT NULL AS posted FROM daily_summaries WHERE date = $1",
+        )
+        .bind(today)
+        .fetch_optional(&self.db)
+        .timed("summary_posted")
+        .await?;
+
+        if posted.is_some_and(|r| r.posted) {
+            return Ok(());
+        }
+
+        let Some(summary) = self.rollup(today).await? else {
+            tracing::warn!("no data to summarise for {today}");
+            return Ok(());
+        };
+        let report = self.report_for(summary).await?;
+        let summary_embed = embed(&report)?;
+
+        // claim the day before sending, so a failure after the channel post
+        // can't have it posted again on the next run
+        let claimed = sqlx::query(
+            "UPDATE daily_summaries SET posted_at = now() WHERE date = $1 AND posted_at IS NULL",
+        )
+        .bind(today)
+        .execute(&self.db)
+        .timed("summary_post_claim")
+        .await?;
+
+        if claimed.rows_affected() == 0 {
+            return Ok(());
+        }
+
+        if let Some(http) = &self.http
+            && let Some(channel_id) = self.channel_id
+            && let Err(e) = http
+                .create_message(channel_id)
+                .embeds(std::slice::from_ref(&summary_embed))
+                .await
+        {
+            // let the next run try again
+            sqlx::query("UPDATE daily_summaries SET posted_at = NULL WHERE date = $1")
+                .bind(today)
+                .execute(&self.db)
+                .timed("summary_post_release")
+                .await?;
+
+            return Err(e.into());
+        }
+
+        if let Err(e) = self
+            .subscriptions
+            .notify(SubscriptionKind::DailySummary, &summary_embed)
+            .await
+        {
+            tracing::error!("error sending daily summary to subscribers: {e}");
+        }
+
+        if let Err(e) = self
+            .webhooks
+            .emit(WebhookEvent::DailySummary, &report)
+            .await
+        {
+            tracing::error!("error queueing daily summary webhooks: {e}");
+        }
+
+        match self.record(&report.summary).await {
+            Ok(Some(previous_best_kwh)) => {
+                if let Err(e) = self
+                    .subscriptions
+                    .notify(
+                        SubscriptionKind::RecordDays,
+                        &record_embed(&report.summary, previous_best_kwh)?,
+                    )
+                    .await
+                {
+                    tracing::error!("error sending record day to subscribers: {e}");
+                }
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("error checking for a record day: {e}"),
+        }
+
+        tracing::info!("posted daily summary for {today}");
+
+        Ok(())
+    }
+
+    /// Returns the previous best total if `summary` beats every earlier day.
+    async fn record(&self, summary: &DailySummary) -> Result<Option<f64>, SummaryError> {
+        #[derive(FromRow)]
+        struct Row {
+            best_kwh: Option<f64>,
+        }
+
+        let row: Row = sqlx::query_as(
+            "SELECT max(total_kwh) AS best_kwh FROM daily_summaries WHERE date < $1",
+        )
+        .bind(summary.date)
+        .fetch_one(&self.db)
+        .timed("summary_best_day")
+        .await?;
+
+        Ok(row
+            .best_kwh
+            .filter(|best_kwh| summary.total_kwh > *best_kwh))
+    }
+}
+
+fn record_embed(summary: &DailySummary, previous_best_kwh: f64) -> Result<Embed, anyhow::Error> {
+    let embed = EmbedBuilder::new()
+        .title(format!("🏆 Record day on {}", summary.date))
+        .description(format!(
+            "{:.2} kWh generated, beating the previous best of {previous_best_kwh:.2} kWh",
+            summary.total_kwh
+        ))
+        .color(0xf9a825)
+        .validate()?
+        .build();
+
+    Ok(embed)
+}
+
+fn forecast_embed(
+    date: NaiveDate,
+    forecast: &DailyForecast,
+    average_kwh: Option<f64>,
+) -> Result<Embed, anyhow::Error> {
+    let embed = EmbedBuilder::new()
+        .title(format!("Forecast for {date}"))
+        .description(
+            forecast
+                .extended_text
+                .as_deref()
+                .or(forecast.short_text.as_deref())
+                .unwrap_or("No forecast text"),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Max temp",
+                forecast
+                    .temp_max
+                    .map(|t| format!("{t:.0}°C"))
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "UV",
+                forecast
+                    .uv
+                    .as_ref()
+                    .and_then(|uv| {
+                        uv.max_index.map(|index| {
+                            format!(
+                                "{index:.0} ({})",
+                                uv.category.as_deref().unwrap_or("unknown")
+                            )
+                        })
+                    })
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Rain chance",
+                forecast
+                    .rain
+                    .as_ref()
+                    .and_then(|rain| rain.chance)
+                    .map(|chance| format!("{chance}%"))
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "30 day avg",
+                average_kwh
+                    .map(|kwh| format!("{kwh:.2} kWh"))
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .color(0x1976d2)
+        .validate()?
+        .build();
+
+    Ok(embed)
+}
+
+fn compare(total_kwh: f64, other_kwh: Option<f64>) -> String {
+    match other_kwh {
+        Some(other) if other > 0.0 => {
+            let percent = (total_kwh - other) / other * 100.0;
+            format!("{other:.2} kWh ({percent:+.0}%)")
+        }
+        Some(other) => format!("{other:.2} kWh"),
+        None => "unknown".to_string(),
+    }
+}
+
+pub fn embed(report: &SummaryReport) -> Result<Embed, anyhow::Error> {
+    let summary = &report.summary;
+    let peak_at = summary.peak_at + Duration::hours(8);
+
+    let embed = EmbedBuilder::new()
+        .title(format!("Solar summary for {}", summary.date))
+        .field(EmbedFieldBuilder::new("Total", format!("{:.2} kWh", summary.total_kwh)).inline())
+        .field(
+            EmbedFieldBuilder::new(
+                "Peak",
+                format!("{} W at {}", summary.peak_w, peak_at.format("%H:%M")),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Savings",
+                summary
+                    .income
+                    .map(|income| {
+                        format!(
+                            "{income:.2} {}",
+                            summary.currency.as_deref().unwrap_or_default()
+                        )
+                    })
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Yesterday",
+                compare(summary.total_kwh, report.yesterday_kwh),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "30 day avg",
+                compare(summary.total_kwh, report.average_30_day_kwh),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Avg UV / temp",
+                format!(
+                    "{} / {}",
+                    summary
+                        .avg_uv_level
+                        .map(|uv| format!("{uv:.1}"))
+                        .unwrap_or("unknown".to_string()),
+                    summary
+                        .avg_temperature
+                        .map(|t| format!("{t:.1}°C"))
+                        .unwrap_or("unknown".to_string())
+                ),
+            )
+            .inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new(
+                "Daylight",
+                sun::sun_times(summary.date, &sun::SITE)
+                    .map(|sun_times| {
+                        let sunrise = sun_times
+                            .sunrise
+                            .with_timezone(&chrono_tz::Australia::Perth);
+                        let sunset = sun_times.sunset.with_timezone(&chrono_tz::Australia::Perth);
+                        format!("{} - {}", sunrise.format("%H:%M"), sunset.format("%H:%M"))
+                    })
+                    .unwrap_or("unknown".to_string()),
+            )
+            .inline(),
+        )
+        .color(0x40944c)
+        .validate()?
+        .build();
+
+    Ok(embed)
+}
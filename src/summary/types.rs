@@ -0,0 +1,22 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct DailySummary {
+    pub date: NaiveDate,
+    pub total_kwh: f64,
+    pub peak_w: f64,
+    pub peak_at: NaiveDateTime,
+    pub avg_uv_level: Option<f64>,
+    pub avg_temperature: Option<f64>,
+    pub income: Option<f64>,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SummaryReport {
+    pub summary: DailySummary,
+    pub yesterday_kwh: Option<f64>,
+    pub average_30_day_kwh: Option<f64>,
+}
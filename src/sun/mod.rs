@@ -0,0 +1,121 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Where the panels are, close enough to the Jandakot weather station.
+pub const SITE: Location = Location {
+    latitude: -32.097,
+    longitude: 115.881,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SunTimes {
+    pub sunrise: DateTime<Utc>,
+    pub sunset: DateTime<Utc>,
+}
+
+const J2000: f64 = 2451545.0;
+const UNIX_EPOCH_JULIAN: f64 = 2440587.5;
+
+fn julian_to_utc(julian: f64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(((julian - UNIX_EPOCH_JULIAN) * 86400.0) as i64, 0)
+}
+
+/// Sunrise and sunset for a local date using the NOAA sunrise equation.
+/// Returns `None` when the sun doesn't rise or set that day.
+pub fn sun_times(date: NaiveDate, location: &Location) -> Option<SunTimes> {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+    let n = (date - epoch).num_days() as f64;
+
+    let mean_solar_time = n - location.longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let m = mean_anomaly.to_radians();
+    let centre = 1.9148 * m.sin() + 0.02 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude = (mean_anomaly + centre + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+
+    let transit =
+        J2000 + mean_solar_time + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+    let declination = (ecliptic_longitude.sin() * 23.4397f64.to_radians().sin()).asin();
+    let latitude = location.latitude.to_radians();
+    let cos_hour_angle = ((-0.833f64).to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+
+    Some(SunTimes {
+        sunrise: julian_to_utc(transit - hour_angle / 360.0)?,
+        sunset: julian_to_utc(transit + hour_angle / 360.0)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, TimeZone};
+
+    use super::*;
+
+    fn perth(date: NaiveDate, hour: u32, min: u32) -> DateTime<Utc> {
+        chrono_tz::Australia::Perth
+            .from_local_datetime(&date.and_time(NaiveTime::from_hms_opt(hour, min, 0).unwrap()))
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn assert_near(actual: DateTime<Utc>, expected: DateTime<Utc>) {
+        let off = (actual - expected).num_seconds().abs();
+        assert!(off <= 3 * 60, "{actual} is {off}s from {expected}");
+    }
+
+    #[test]
+    fn matches_published_perth_times() {
+        // published times for Perth, which is a few km from the site
+        for ((y, m, d), sunrise, sunset) in [
+            ((2025, 6, 21), (7, 16), (17, 20)),
+            ((2025, 12, 22), (5, 9), (19, 23)),
+            ((2026, 3, 20), (6, 20), (18, 29)),
+        ] {
+            let date = NaiveDate::from_ymd_opt(y, m, d).unwrap();
+            let times = sun_times(date, &SITE).unwrap();
+
+            assert_near(times.sunrise, perth(date, sunrise.0, sunrise.1));
+            assert_near(times.sunset, perth(date, sunset.0, sunset.1));
+        }
+    }
+
+    #[test]
+    fn sunrise_and_sunset_fall_on_the_local_date() {
+        let date = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+        let times = sun_times(date, &SITE).unwrap();
+
+        for at in [times.sunrise, times.sunset] {
+            assert_eq!(
+                at.with_timezone(&chrono_tz::Australia::Perth).date_naive(),
+                date
+            );
+        }
+    }
+
+    #[test]
+    fn none_during_polar_night_and_day() {
+        let arctic = Location {
+            latitude: 80.0,
+            longitude: 15.0,
+        };
+
+        for (m, d) in [(6, 21), (12, 21)] {
+            let date = NaiveDate::from_ymd_opt(2026, m, d).unwrap();
+            assert!(sun_times(date, &arctic).is_none());
+        }
+    }
+}
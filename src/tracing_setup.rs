@@ -1,21 +1,27 @@
-use http::{HeaderMap, HeaderValue};
+use crate::config::types::{LogFormat, OtlpProtocol, TelemetryConfig};
 use opentelemetry::{KeyValue, global, trace::TracerProvider};
-use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_otlp::{
+    MetricExporter, Protocol, SpanExporter, WithExportConfig, WithHttpConfig,
+};
 use opentelemetry_sdk::{
     Resource,
+    metrics::SdkMeterProvider,
     propagation::TraceContextPropagator,
     trace::BatchConfigBuilder,
-    trace::{BatchSpanProcessor, Tracer},
+    trace::{BatchSpanProcessor, SdkTracerProvider, Tracer},
 };
 use opentelemetry_semantic_conventions::resource::{
     DEPLOYMENT_ENVIRONMENT_NAME, SERVICE_NAME, TELEMETRY_SDK_LANGUAGE, TELEMETRY_SDK_NAME,
     TELEMETRY_SDK_VERSION,
 };
 use reqwest_tracing::{ReqwestOtelSpanBackend, default_on_request_end, reqwest_otel_span};
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 use tokio::time::Instant;
-use tracing::{Level, Span};
-use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing::Span;
+use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Reported as the OpenTelemetry service and tracer name.
+const SERVICE: &str = "solar-panels";
 
 pub struct TimeTrace;
 impl ReqwestOtelSpanBackend for TimeTrace {
@@ -44,20 +50,12 @@ impl ReqwestOtelSpanBackend for TimeTrace {
     }
 }
 
-pub fn external_tracer() -> Tracer {
-    let ingest_url = std::env::var("OTEL_TRACING_URL").unwrap();
-
-    let mut headers = HeaderMap::<HeaderValue>::with_capacity(1);
-    headers.insert(
-        "User-Agent",
-        HeaderValue::from_str(&format!("solar-panels/{}", env!("CARGO_PKG_VERSION"))).unwrap(),
-    );
-
+fn resource() -> Resource {
     let tags = vec![
         KeyValue::new(TELEMETRY_SDK_NAME, "otel-tracing-rs".to_string()),
         KeyValue::new(TELEMETRY_SDK_VERSION, env!("CARGO_PKG_VERSION").to_string()),
         KeyValue::new(TELEMETRY_SDK_LANGUAGE, "rust".to_string()),
-        KeyValue::new(SERVICE_NAME, format!("solar-panels")),
+        KeyValue::new(SERVICE_NAME, SERVICE.to_string()),
         KeyValue::new(
             DEPLOYMENT_ENVIRONMENT_NAME,
             if cfg!(debug_assertions) {
@@ -68,47 +66,144 @@ pub fn external_tracer() -> Tracer {
         ),
     ];
 
-    let resource = Resource::builder_empty().with_attributes(tags).build();
+    Resource::builder_empty().with_attributes(tags).build()
+}
+
+fn user_agent() -> HashMap<String, String> {
+    HashMap::from([(
+        "User-Agent".to_string(),
+        format!("{SERVICE}/{}", env!("CARGO_PKG_VERSION")),
+    )])
+}
 
+fn http_protocol(protocol: OtlpProtocol) -> Protocol {
+    match protocol {
+        OtlpProtocol::HttpJson => Protocol::HttpJson,
+        OtlpProtocol::Grpc | OtlpProtocol::HttpProtobuf => Protocol::HttpBinary,
+    }
+}
+
+pub fn external_tracer(
+    endpoint: &str,
+    protocol: OtlpProtocol,
+) -> anyhow::Result<(Tracer, SdkTracerProvider)> {
     let batch_config = BatchConfigBuilder::default()
         .with_max_queue_size(20480)
         .build();
 
-    let span_exporter = opentelemetry_otlp::HttpExporterBuilder::default()
-        .with_protocol(Protocol::HttpJson)
-        .with_endpoint(ingest_url)
-        .with_timeout(Duration::from_secs(3))
-        .build_span_exporter()
-        .unwrap();
+    let span_exporter = match protocol {
+        OtlpProtocol::Grpc => SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_timeout(Duration::from_secs(3))
+            .build()?,
+        OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => SpanExporter::builder()
+            .with_http()
+            .with_protocol(http_protocol(protocol))
+            .with_endpoint(endpoint)
+            .with_headers(user_agent())
+            .with_timeout(Duration::from_secs(3))
+            .build()?,
+    };
 
-    let tracer_provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+    let tracer_provider = SdkTracerProvider::builder()
         .with_span_processor(
             BatchSpanProcessor::builder(span_exporter)
                 .with_batch_config(batch_config)
                 .build(),
         )
-        .with_resource(resource)
+        .with_resource(resource())
+        .build();
+
+    let tracer = tracer_provider.tracer(SERVICE);
+    global::set_tracer_provider(tracer_provider.clone());
+
+    Ok((tracer, tracer_provider))
+}
+
+pub fn external_meter(endpoint: &str, protocol: OtlpProtocol) -> anyhow::Result<SdkMeterProvider> {
+    let metric_exporter = match protocol {
+        OtlpProtocol::Grpc => MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_timeout(Duration::from_secs(3))
+            .build()?,
+        OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => MetricExporter::builder()
+            .with_http()
+            .with_protocol(http_protocol(protocol))
+            .with_endpoint(endpoint)
+            .with_headers(user_agent())
+            .with_timeout(Duration::from_secs(3))
+            .build()?,
+    };
+
+    let meter_provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(metric_exporter)
+        .with_resource(resource())
         .build();
 
-    let tracer = tracer_provider.tracer("home-gateway");
-    global::set_tracer_provider(tracer_provider);
+    global::set_meter_provider(meter_provider.clone());
 
-    tracer
+    Ok(meter_provider)
 }
 
-pub fn init() {
-    let tracer = external_tracer();
+/// The exporters installed by [`init`]. These must be shut down before exit
+/// so buffered spans and metrics are flushed.
+pub struct Telemetry {
+    tracer_provider: Option<SdkTracerProvider>,
+    meter_provider: Option<SdkMeterProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        if let Some(Err(e)) = self.tracer_provider.map(|provider| provider.shutdown()) {
+            eprintln!("error flushing traces: {e}");
+        }
+
+        if let Some(Err(e)) = self.meter_provider.map(|provider| provider.shutdown()) {
+            eprintln!("error flushing metrics: {e}");
+        }
+    }
+}
+
+/// Installs the global subscriber. Spans and metrics are only exported when
+/// an OTLP endpoint is configured.
+pub fn init(config: &TelemetryConfig) -> anyhow::Result<Telemetry> {
+    let exporter = config
+        .otlp_endpoint
+        .as_deref()
+        .map(|endpoint| external_tracer(endpoint, config.otlp_protocol))
+        .transpose()?;
+    let (tracer, tracer_provider) = exporter.unzip();
+    // keep the OpenTelemetry layer installed without an exporter so trace
+    // context still propagates and the axum middleware can find it
+    let tracer = tracer.unwrap_or_else(|| SdkTracerProvider::builder().build().tracer(SERVICE));
+
+    let meter_provider = config
+        .metrics_endpoint()
+        .map(|endpoint| external_meter(&endpoint, config.otlp_protocol))
+        .transpose()?;
 
     opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
 
+    let fmt_layer = match config.log_format {
+        LogFormat::Console => tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(std::io::stderr)
+            .boxed(),
+    };
+
     tracing_subscriber::registry()
-        .with(
-            Targets::default()
-                .with_target("otel::tracing", Level::TRACE)
-                .with_target("sea_orm::database", Level::TRACE)
-                .with_default(Level::INFO),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        .with(EnvFilter::try_new(&config.log_filter)?)
+        .with(fmt_layer)
         .with(tracing_opentelemetry::layer().with_tracer(tracer))
         .init();
+
+    Ok(Telemetry {
+        tracer_provider,
+        meter_provider,
+    })
 }
@@ -1,6 +1,13 @@
-use axum::{response::IntoResponse, response::Response};
+use axum::{
+    Json,
+    extract::{FromRequestParts, Query},
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
 use chrono::NaiveDateTime;
-use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+
+use crate::{anomaly::types::Anomaly, metrics::Upstream};
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -52,27 +59,118 @@ pub struct SolarHistoryV2Response {
     pub history: Vec<GenerationHistory>,
 }
 
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnomaliesResponse {
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// An error from the http api, returned as a problem details body
+/// (RFC 9457). Internal causes are logged and never returned.
+#[derive(Debug)]
 pub enum AppError {
-    Error(anyhow::Error),
+    NotFound(String),
+    BadRequest(String),
+    Forbidden(String),
+    UpstreamUnavailable {
+        upstream: Upstream,
+        source: anyhow::Error,
+    },
+    Internal(anyhow::Error),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
+#[derive(serde::Serialize)]
+struct Problem {
+    title: &'static str,
+    status: u16,
+    /// Stable across releases, for clients to match on.
+    code: &'static str,
+    detail: String,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::UpstreamUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
         match self {
-            AppError::Error(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Something went wrong: {}", e),
-            )
-                .into_response(),
+            AppError::NotFound(_) => "not_found",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::UpstreamUnavailable { .. } => "upstream_unavailable",
+            AppError::Internal(_) => "internal",
         }
     }
 }
 
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let code = self.code();
+        let detail = match self {
+            AppError::NotFound(detail)
+            | AppError::BadRequest(detail)
+            | AppError::Forbidden(detail) => detail,
+            AppError::UpstreamUnavailable { upstream, source } => {
+                tracing::error!(
+                    "{} unavailable handling request: {source:#}",
+                    upstream.as_str()
+                );
+                format!("{} is unavailable, try again later", upstream.as_str())
+            }
+            AppError::Internal(e) => {
+                tracing::error!("error handling request: {e:#}");
+                "an internal error occurred".to_string()
+            }
+        };
+
+        let problem = Problem {
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            code,
+            detail,
+        };
+
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(problem),
+        )
+            .into_response()
+    }
+}
+
+/// Anything else that goes wrong while handling a request is internal.
 impl<E> From<E> for AppError
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self::Error(err.into())
+        Self::Internal(err.into())
+    }
+}
+
+/// `Query`, but rejecting malformed parameters with an `AppError`.
+pub struct ApiQuery<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Query::<T>::from_request_parts(parts, state)
+            .await
+            .map(|Query(params)| Self(params))
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))
     }
 }
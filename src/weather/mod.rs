@@ -1,5 +1,5 @@
 use tracing::instrument;
-use types::{UVXMLDocument, WeatherDetails};
+use types::{DailyForecast, DailyForecastResponse, UVXMLDocument, WeatherDetails};
 
 #[derive(Clone, Debug)]
 pub struct WeatherAPI {
@@ -21,6 +21,8 @@ pub enum WeatherAPIError {
 impl WeatherAPI {
     const UV_LEVELS_XML: &str = "https://uvdata.arpansa.gov.au/xml/uvvalues.xml";
     const WEATHER_API_DETAILS: &str = "https://api.weather.bom.gov.au/v1/locations/{}/observations";
+    const WEATHER_API_DAILY_FORECAST: &str =
+        "https://api.weather.bom.gov.au/v1/locations/{}/forecasts/daily";
     pub const PERTH_NAME: &str = "per";
     pub const JANDAKOT_GEOCODE: &str = "qd63he";
 
@@ -49,6 +51,31 @@ impl WeatherAPI {
         Ok(weather_details)
     }
 
+    /// Today's forecast, the first entry of the BOM daily forecast.
+    #[instrument(skip(self))]
+    pub async fn get_daily_forecast(
+        &self,
+        geocode: &str,
+    ) -> Result<DailyForecast, WeatherAPIError> {
+        let forecast = self
+            .http
+            .get(Self::WEATHER_API_DAILY_FORECAST.replace("{}", geocode))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<DailyForecastResponse>()
+            .await?;
+
+        tracing::info!("fetched daily forecast");
+
+        forecast
+            .data
+            .into_iter()
+            .next()
+            .ok_or(anyhow::Error::msg("forecast is empty"))
+            .map_err(Into::into)
+    }
+
     #[instrument(skip(self))]
     pub async fn get_uv_level(&self, name: &str) -> Result<f64, WeatherAPIError> {
         let uv_levels_xml = self
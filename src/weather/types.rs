@@ -112,3 +112,39 @@ pub struct Station {
     pub name: String,
     pub distance: i64,
 }
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyForecastResponse {
+    pub data: Vec<DailyForecast>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyForecast {
+    pub date: String,
+    #[serde(rename = "temp_max")]
+    pub temp_max: Option<f64>,
+    #[serde(rename = "temp_min")]
+    pub temp_min: Option<f64>,
+    #[serde(rename = "short_text")]
+    pub short_text: Option<String>,
+    #[serde(rename = "extended_text")]
+    pub extended_text: Option<String>,
+    pub rain: Option<ForecastRain>,
+    pub uv: Option<ForecastUv>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForecastRain {
+    pub chance: Option<i64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForecastUv {
+    pub category: Option<String>,
+    #[serde(rename = "max_index")]
+    pub max_index: Option<f64>,
+}
@@ -0,0 +1,303 @@
+use std::{sync::Arc, time::Duration, time::Instant};
+
+use chrono::Utc;
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest_tracing::TracingMiddleware;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::{PgConnection, PgPool};
+use tracing::instrument;
+use types::{Envelope, WebhookDelivery, WebhookEvent};
+
+use crate::{
+    config::types::WebhookConfig,
+    metrics::Timed,
+    outbox::{Outbox, OutboxError, types::OutboxEntry},
+    tracing_setup::TimeTrace,
+};
+
+pub mod types;
+
+/// Outbox destinations for webhooks are this prefix followed by the target name.
+pub const DESTINATION_PREFIX: &str = "webhook:";
+
+/// Fans events out to the configured webhook targets through the outbox.
+#[derive(Clone)]
+pub struct Webhooks {
+    db: PgPool,
+    targets: Arc<[WebhookConfig]>,
+    http_client: reqwest_middleware::ClientWithMiddleware,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebhookError {
+    #[error("a database error occurred: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("an outbox error occurred: {0}")]
+    Outbox(#[from] OutboxError),
+}
+
+impl Webhooks {
+    pub fn new(db: PgPool, targets: Vec<WebhookConfig>) -> Self {
+        Self {
+            db,
+            targets: targets.into(),
+            http_client: reqwest_middleware::ClientBuilder::new(
+                reqwest::ClientBuilder::new()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .unwrap(),
+            )
+            .with(TracingMiddleware::<TimeTrace>::new())
+            .build(),
+        }
+    }
+
+    fn subscribers(&self, event: WebhookEvent) -> impl Iterator<Item = &WebhookConfig> {
+        self.targets
+            .iter()
+            .filter(move |target| target.events.contains(&event))
+    }
+
+    /// Queues `data` for every target subscribed to `event`.
+    pub async fn emit(
+        &self,
+        event: WebhookEvent,
+        data: &impl Serialize,
+    ) -> Result<(), WebhookError> {
+        if self.subscribers(event).next().is_none() {
+            return Ok(());
+        }
+
+        let mut conn = self.db.acquire().await?;
+        self.enqueue(&mut conn, event, data).await
+    }
+
+    /// Like [`Webhooks::emit`], but within the caller's transaction.
+    pub async fn enqueue(
+        &self,
+        conn: &mut PgConnection,
+        event: WebhookEvent,
+        data: &impl Serialize,
+    ) -> Result<(), WebhookError> {
+        let envelope = Envelope {
+            event,
+            timestamp: Utc::now(),
+            data,
+        };
+
+        for target in self.subscribers(event) {
+            let destination = format!("{DESTINATION_PREFIX}{}", target.name);
+            Outbox::enqueue(&mut *conn, &destination, &envelope).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends an outbox entry to the named target, recording the attempt in
+    /// its delivery log.
+    #[instrument(skip(self, entry), fields(outbox_id = entry.id))]
+    pub async fn deliver(&self, name: &str, entry: &OutboxEntry) -> Result<(), String> {
+        let Some(target) = self.targets.iter().find(|target| target.name == name) else {
+            return Err(format!("webhook {name} is not configured"));
+        };
+
+        let body = match &target.template {
+            Some(template) => render(
+                template,
+                &entry.payload,
+                target.content_type.contains("json"),
+            ),
+            None => entry.payload.to_string(),
+        };
+        let event = entry
+            .payload
+            .get("event")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let timestamp = Utc::now().timestamp();
+
+        let started = Instant::now();
+        let response = self
+            .http_client
+            .post(&target.url)
+            .header(CONTENT_TYPE, &target.content_type)
+            .header("X-Solar-Event", event)
+            .header("X-Solar-Delivery", entry.id)
+            .header(
+                "X-Solar-Signature",
+                signature(&target.secret, timestamp, &body),
+            )
+            .body(body)
+            .send()
+            .await;
+        let elapsed = started.elapsed();
+
+        let (status_code, result) = match response {
+            Ok(response) => {
+                let status = response.status();
+                let result = if status.is_success() {
+                    Ok(())
+                } else {
+                    Err(format!("webhook {name} responded with {status}"))
+                };
+                (Some(i32::from(status.as_u16())), result)
+            }
+            Err(e) => (None, Err(e.to_string())),
+        };
+
+        if let Err(e) = sqlx::query(
+            r#"INSERT INTO webhook_deliveries (webhook, outbox_id, event, status_code, error, duration_ms)
+               VALUES ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(name)
+        .bind(entry.id)
+        .bind(event)
+        .bind(status_code)
+        .bind(result.as_ref().err())
+        .bind(i32::try_from(elapsed.as_millis()).unwrap_or(i32::MAX))
+        .execute(&self.db)
+        .timed("webhook_delivery_log")
+        .await
+        {
+            tracing::warn!("error recording delivery to webhook {name}: {e}");
+        }
+
+        result
+    }
+
+    /// The most recent delivery attempts, optionally for a single target.
+    pub async fn deliveries(
+        &self,
+        name: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, WebhookError> {
+        let deliveries = sqlx::query_as(
+            r#"SELECT webhook, outbox_id, event, status_code, error, duration_ms, attempted_at
+               FROM webhook_deliveries
+               WHERE ($1::text IS NULL OR webhook = $1)
+               ORDER BY attempted_at DESC
+               LIMIT $2"#,
+        )
+        .bind(name)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .timed("webhook_deliveries")
+        .await?;
+
+        Ok(deliveries)
+    }
+}
+
+/// The `X-Solar-Signature` header value, `t=<timestamp>,v1=<signature>`.
+fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    format!("t={timestamp},v1={}", sign(secret, timestamp, body))
+}
+
+/// Signs `<timestamp>.<body>`, so receivers can reject replayed deliveries.
+fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Replaces each `{{path}}` with the value at that dotted path in the
+/// envelope. Strings are inserted without quotes, other values as JSON and
+/// missing values as nothing. With `escape_json` set, strings are escaped so
+/// they can sit between quotes in a JSON template.
+fn render(template: &str, envelope: &Value, escape_json: bool) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+
+        out.push_str(&rest[..start]);
+        let path = rest[start + 2..start + len].trim();
+        let pointer: String = path
+            .split('.')
+            .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+            .collect();
+        match envelope.pointer(&pointer) {
+            Some(value @ Value::String(_)) if escape_json => {
+                let quoted = value.to_string();
+                out.push_str(&quoted[1..quoted.len() - 1]);
+            }
+            Some(Value::String(value)) => out.push_str(value),
+            Some(Value::Null) | None => {}
+            Some(value) => out.push_str(&value.to_string()),
+        }
+
+        rest = &rest[start + len + 2..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn signature_header_carries_timestamp_and_hmac() {
+        assert_eq!(
+            signature("shh", 1_700_000_000, r#"{"event":"sample"}"#),
+            "t=1700000000,v1=61a04d03710990051012a254d66a239ca98cb072f3df80e7c15565b2b6c230df"
+        );
+    }
+
+    #[test]
+    fn render_fills_placeholders() {
+        let envelope = json!({
+            "event": "sample",
+            "data": { "power_w": 1250.5, "station": null, "tags": ["a"] },
+        });
+
+        assert_eq!(
+            render(
+                "{{event}}: {{ data.power_w }} W {{data.station}}{{data.missing}}{{data.tags}}",
+                &envelope,
+                false
+            ),
+            r#"sample: 1250.5 W ["a"]"#
+        );
+        // an unclosed placeholder is left as it is
+        assert_eq!(render("{{event", &envelope, false), "{{event");
+    }
+
+    #[test]
+    fn render_escapes_strings_for_json() {
+        let envelope = json!({ "data": { "message": "line \"one\"\nline\\two" } });
+        let template = r#"{"text": "{{data.message}}"}"#;
+
+        let body = render(template, &envelope, true);
+        let parsed: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["text"], envelope["data"]["message"]);
+
+        assert_eq!(
+            render(template, &envelope, false),
+            "{\"text\": \"line \"one\"\nline\\two\"}"
+        );
+    }
+
+    #[test]
+    fn render_escapes_pointer_characters_in_keys() {
+        let envelope = json!({ "data": { "a/b": 1, "c~d": 2, "a": { "b": 3 } } });
+
+        assert_eq!(
+            render("{{data.a/b}} {{data.c~d}} {{data.a.b}}", &envelope, false),
+            "1 2 3"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use crate::anomaly::types::Anomaly;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A new reading was stored.
+    Sample,
+    DailySummary,
+    /// An alert rule fired or recovered.
+    Alert,
+    /// An anomaly was opened or resolved.
+    Anomaly,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 4] = [
+        WebhookEvent::Sample,
+        WebhookEvent::DailySummary,
+        WebhookEvent::Alert,
+        WebhookEvent::Anomaly,
+    ];
+}
+
+/// The body stored in the outbox and, without a template, sent as is.
+#[derive(Debug, Serialize)]
+pub struct Envelope<'a, T> {
+    pub event: WebhookEvent,
+    pub timestamp: DateTime<Utc>,
+    pub data: &'a T,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlertEvent<'a> {
+    pub rule: &'static str,
+    pub title: &'static str,
+    /// `fired` or `resolved`.
+    pub status: &'static str,
+    pub message: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnomalyEvent<'a> {
+    /// `opened` or `resolved`.
+    pub status: &'static str,
+    pub kind: &'a str,
+    pub details: &'a str,
+    pub expected_wh: Option<f64>,
+    pub actual_wh: Option<f64>,
+    pub started_at: NaiveDateTime,
+    pub resolved_at: Option<NaiveDateTime>,
+}
+
+impl<'a> AnomalyEvent<'a> {
+    pub fn new(status: &'static str, anomaly: &'a Anomaly) -> Self {
+        Self {
+            status,
+            kind: &anomaly.kind,
+            details: &anomaly.details,
+            expected_wh: anomaly.expected_wh,
+            actual_wh: anomaly.actual_wh,
+            started_at: anomaly.started_at,
+            resolved_at: anomaly.resolved_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct WebhookDelivery {
+    pub webhook: String,
+    pub outbox_id: i64,
+    pub event: String,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    pub attempted_at: NaiveDateTime,
+}